@@ -0,0 +1,129 @@
+//! Folds channel names and nicks to the form used as keys, following the
+//! case mapping the server advertises with `CASEMAPPING` in its ISUPPORT
+//! (005) reply. On IRC, `#Rust` and `#rust` are the same channel, and with
+//! the RFC 1459 mapping so are `[nick]` and `{nick}`, so keys that differ
+//! only that way must be the same for settings to be found again.
+//!
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+/// The ways servers compare names, as advertised with `CASEMAPPING`.
+///
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CaseMapping {
+    /// Only the letters A to Z are folded.
+    Ascii,
+    /// The letters, and `[]\~` as the upper case of `{}|^`.
+    Rfc1459,
+    /// The letters, and `[]\` as the upper case of `{}|`.
+    StrictRfc1459,
+}
+
+impl CaseMapping {
+    /// Parses the value of the `CASEMAPPING` token. Mappings the plugin
+    /// doesn't know, like "rfc7613", give `None`.
+    ///
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "ascii"          => Some(CaseMapping::Ascii),
+            "rfc1459"        => Some(CaseMapping::Rfc1459),
+            "strict-rfc1459" => Some(CaseMapping::StrictRfc1459),
+            _                => None,
+        }
+    }
+
+    /// Folds `name` to lower case under this mapping.
+    ///
+    pub fn fold(self, name: &str) -> String {
+        name.chars()
+            .map(|c| match (self, c) {
+                (_, 'A'..='Z')  => c.to_ascii_lowercase(),
+                (CaseMapping::Ascii, _) => c,
+                (_, '[')        => '{',
+                (_, ']')        => '}',
+                (_, '\\')       => '|',
+                (CaseMapping::Rfc1459, '~') => '^',
+                _               => c,
+            })
+            .collect()
+    }
+}
+
+/// The case mapping of each network, keyed by network name.
+///
+fn mappings() -> &'static Mutex<HashMap<String, CaseMapping>> {
+    static MAPPINGS: OnceLock<Mutex<HashMap<String, CaseMapping>>>
+        = OnceLock::new();
+    MAPPINGS.get_or_init(Mutex::default)
+}
+
+/// Records the case mapping `network` advertises.
+///
+pub fn set_mapping(network: &str, mapping: CaseMapping) {
+    mappings().lock().unwrap().insert(network.to_string(), mapping);
+}
+
+/// Gives the case mapping of `network`. Until the server says otherwise,
+/// it's RFC 1459, the protocol's default.
+///
+pub fn mapping(network: &str) -> CaseMapping {
+    mappings().lock()
+              .unwrap()
+              .get(network)
+              .copied()
+              .unwrap_or(CaseMapping::Rfc1459)
+}
+
+/// Folds the channel name or nick `name` on `network` to the form used as
+/// its key.
+///
+pub fn fold(network: &str, name: &str) -> String {
+    mapping(network).fold(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc1459_folds_brackets_backslash_and_tilde() {
+        let rfc = CaseMapping::Rfc1459;
+        assert_eq!(rfc.fold("#Rust"), "#rust");
+        assert_eq!(rfc.fold("[Dev]\\~"), "{dev}|^");
+        assert_eq!(rfc.fold("[nick]"), rfc.fold("{NICK}"));
+    }
+
+    #[test]
+    fn strict_rfc1459_leaves_tilde() {
+        let strict = CaseMapping::StrictRfc1459;
+        assert_eq!(strict.fold("[A]\\~"), "{a}|~");
+    }
+
+    #[test]
+    fn ascii_folds_only_letters() {
+        let ascii = CaseMapping::Ascii;
+        assert_eq!(ascii.fold("[ABC]\\~"), "[abc]\\~");
+    }
+
+    #[test]
+    fn non_ascii_letters_are_left_alone() {
+        assert_eq!(CaseMapping::Rfc1459.fold("#ÜBER"), "#Über");
+    }
+
+    #[test]
+    fn parses_known_mappings() {
+        assert_eq!(CaseMapping::parse("RFC1459"), Some(CaseMapping::Rfc1459));
+        assert_eq!(CaseMapping::parse("strict-rfc1459"),
+                   Some(CaseMapping::StrictRfc1459));
+        assert_eq!(CaseMapping::parse("ascii"), Some(CaseMapping::Ascii));
+        assert_eq!(CaseMapping::parse("rfc7613"), None);
+    }
+
+    #[test]
+    fn networks_default_to_rfc1459_until_told() {
+        assert_eq!(fold("test-net-default", "#[X]"), "#{x}");
+        set_mapping("test-net-ascii", CaseMapping::Ascii);
+        assert_eq!(fold("test-net-ascii", "#[X]"), "#[x]");
+    }
+}
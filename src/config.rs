@@ -0,0 +1,521 @@
+//! Persistent settings for the translator. The settings are kept in a JSON
+//! file in Hexchat's config directory, loaded when the plugin starts, and
+//! written back each time a command changes them.
+//!
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use hexchat_api::Hexchat;
+
+use crate::casemap;
+use crate::context::MAX_LINES;
+use crate::rules::{Direction, Rule};
+
+/// The name of the settings file within Hexchat's config directory.
+///
+const CONFIG_FILE_NAME: &str = "addon_translator.json";
+
+/// The number of recent lines sent as context with each translation,
+/// unless changed for the channel.
+///
+pub const DEFAULT_CONTEXT_LINES: usize = 3;
+
+/// The formality levels DeepL accepts. "default" leaves it to DeepL.
+///
+pub const FORMALITY_LEVELS: [&str; 5] = ["default", "more", "less", 
+                                         "prefer_more", "prefer_less"];
+
+/// The plugin's persistent settings. Fields missing from the file take
+/// their default values, so older files continue to load as new settings
+/// are added.
+///
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct Config {
+    /// Maps network names to a map of nicks (folded, see `casemap::fold()`)
+    /// to the language code that nick's messages are translated from.
+    pub user_langs: HashMap<String, HashMap<String, String>>,
+
+    /// Maps network names to a map of channel names (folded, see 
+    /// `casemap::fold()`) to the settings made for that channel with 
+    /// `/LSET`.
+    pub channels: HashMap<String, HashMap<String, ChannelSettings>>,
+
+    /// The rewrite rules added with `/LRULE`, in the order they're applied.
+    pub rules: Vec<Rule>,
+
+    /// Maps network names to a map of nicks (folded) to the formality
+    /// to use in messages to that nick, set with `/LFORMALITY`.
+    pub user_formality: HashMap<String, HashMap<String, String>>,
+
+    /// The DeepL options used in channels that don't set their own, set 
+    /// with `/LSET -global`.
+    pub deepl: DeepLOptions,
+}
+
+/// Per-channel settings, changed with `/LSET`.
+///
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ChannelSettings {
+    /// When the channel has several target languages, send each translation
+    /// on its own line instead of combining them into one.
+    pub separate_lines: bool,
+
+    /// Translate plain text typed into the channel as if it were sent with
+    /// `/LSAY`.
+    pub auto_say: bool,
+
+    /// Preview `/LSAY` and `/LME` messages like `/LPREVIEW` does, rather
+    /// than sending them.
+    pub confirm: bool,
+
+    /// Translate sent messages back into the user's language to show how
+    /// they came across.
+    pub back_check: bool,
+
+    /// Translate notices sent to the user or the channel.
+    pub notices: bool,
+
+    /// Translate the channel's topic when joining and when it changes. On
+    /// by default, as the topic is often the most useful line in a channel.
+    pub topics: bool,
+
+    /// Translate the reasons given for parting the channel.
+    pub parts: bool,
+
+    /// Translate the reasons given for quitting.
+    pub quits: bool,
+
+    /// Translate the reasons given for kicks.
+    pub kicks: bool,
+
+    /// The IDs of the DeepL glossaries attached to the channel with 
+    /// `/LGLOSSARY USE`, keyed by their language pair. See 
+    /// `glossary::pair_key()`.
+    pub glossaries: HashMap<String, String>,
+
+    /// The formality translations into the channel's languages use, for 
+    /// languages that support it. `None` leaves it to DeepL.
+    pub formality: Option<String>,
+
+    /// The number of recent lines of the conversation sent as context with
+    /// each translation. `None` uses `DEFAULT_CONTEXT_LINES`.
+    pub context_lines: Option<usize>,
+
+    /// The DeepL options set for the channel. Those not set fall back to 
+    /// the global ones.
+    pub deepl: DeepLOptions,
+}
+
+/// Options passed on to DeepL with each translation. An option that's
+/// `None` isn't sent, leaving it to DeepL's default.
+///
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct DeepLOptions {
+    /// Whether DeepL favors speed or quality: "latency_optimized", 
+    /// "quality_optimized" or "prefer_quality_optimized".
+    pub model_type: Option<String>,
+
+    /// How DeepL splits the text into sentences: "0" (not at all), "1" (at
+    /// punctuation and newlines) or "nonewlines" (at punctuation only).
+    pub split_sentences: Option<String>,
+
+    /// Whether DeepL keeps the text's punctuation and capitalization rather
+    /// than correcting them.
+    pub preserve_formatting: Option<bool>,
+}
+
+impl DeepLOptions {
+    /// Sets the option `name` to the value given as text. "default" unsets
+    /// it.
+    /// # Returns
+    /// * `Err()` with a description of the problem if the option doesn't
+    ///   exist or the value isn't valid for it.
+    ///
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let value   = value.to_lowercase();
+        let default = value == "default";
+        match name.to_lowercase().as_str() {
+            "model" => {
+                self.model_type = match value.as_str() {
+                    _ if default => None,
+                    "latency" | "latency_optimized" => {
+                        Some("latency_optimized".into())
+                    },
+                    "quality" | "quality_optimized" => {
+                        Some("quality_optimized".into())
+                    },
+                    "prefer_quality" | "prefer_quality_optimized" => {
+                        Some("prefer_quality_optimized".into())
+                    },
+                    _ => return Err(format!("'{}' isn't a valid model; use \
+                                             latency, quality, \
+                                             prefer_quality or default.", 
+                                            value)),
+                };
+            },
+            "split" => {
+                self.split_sentences = match value.as_str() {
+                    _ if default => None,
+                    "off" | "0"  => Some("0".into()),
+                    "on"  | "1"  => Some("1".into()),
+                    "nonewlines" => Some("nonewlines".into()),
+                    _ => return Err(format!("'{}' isn't a valid value; use \
+                                             on, off, nonewlines or \
+                                             default.", value)),
+                };
+            },
+            "preserve" => {
+                self.preserve_formatting = if default { 
+                    None 
+                } else { 
+                    Some(parse_bool(&value)?) 
+                };
+            },
+            _ => return Err(format!("Unknown option '{}'.", name)),
+        }
+        Ok(())
+    }
+
+    /// Lists the options with their current values as text, for display.
+    ///
+    pub fn options(&self) -> Vec<(&'static str, String)> {
+        let or_default = |v: Option<String>| v.unwrap_or("default".into());
+        vec![
+            ("model",    or_default(self.model_type.clone())),
+            ("split",    or_default(self.split_sentences.clone())),
+            ("preserve", or_default(self.preserve_formatting.map(on_off))),
+        ]
+    }
+
+    /// Gives these options, with those not set taken from `fallback`.
+    ///
+    pub fn or(&self, fallback: &DeepLOptions) -> DeepLOptions {
+        DeepLOptions {
+            model_type          : self.model_type.clone()
+                                      .or(fallback.model_type.clone()),
+            split_sentences     : self.split_sentences.clone()
+                                      .or(fallback.split_sentences.clone()),
+            preserve_formatting : self.preserve_formatting
+                                      .or(fallback.preserve_formatting),
+        }
+    }
+}
+
+impl Default for ChannelSettings {
+    fn default() -> Self {
+        ChannelSettings {
+            separate_lines : false,
+            auto_say       : false,
+            confirm        : false,
+            back_check     : false,
+            notices        : false,
+            topics         : true,
+            parts          : false,
+            quits          : false,
+            kicks          : false,
+            glossaries     : HashMap::new(),
+            formality      : None,
+            context_lines  : None,
+            deepl          : DeepLOptions::default(),
+        }
+    }
+}
+
+impl ChannelSettings {
+    /// Sets the option `name` to the value given as text.
+    /// # Returns
+    /// * `Err()` with a description of the problem if the option doesn't
+    ///   exist or the value isn't valid for it.
+    ///
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name.to_lowercase().as_str() {
+            "separate"  => self.separate_lines = parse_bool(value)?,
+            "autosay"   => self.auto_say       = parse_bool(value)?,
+            "confirm"   => self.confirm        = parse_bool(value)?,
+            "backcheck" => self.back_check     = parse_bool(value)?,
+            "notices"   => self.notices        = parse_bool(value)?,
+            "topics"    => self.topics         = parse_bool(value)?,
+            "parts"     => self.parts          = parse_bool(value)?,
+            "quits"     => self.quits          = parse_bool(value)?,
+            "kicks"     => self.kicks          = parse_bool(value)?,
+            "formality" => self.formality      = parse_formality(value)?,
+            "context"   => self.context_lines  = Some(parse_count(value)?),
+            _ => return self.deepl.set(name, value),
+        }
+        Ok(())
+    }
+
+    /// Lists the options with their current values as text, for display.
+    ///
+    pub fn options(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("separate",  on_off(self.separate_lines)),
+            ("autosay",   on_off(self.auto_say)),
+            ("confirm",   on_off(self.confirm)),
+            ("backcheck", on_off(self.back_check)),
+            ("notices",   on_off(self.notices)),
+            ("topics",    on_off(self.topics)),
+            ("parts",     on_off(self.parts)),
+            ("quits",     on_off(self.quits)),
+            ("kicks",     on_off(self.kicks)),
+            ("formality", self.formality.clone()
+                                        .unwrap_or_else(|| "default".into())),
+            ("context",   self.context_lines().to_string()),
+        ].into_iter()
+         .chain(self.deepl.options())
+         .collect()
+    }
+
+    /// Indicates whether the text of the events turned on by the on/off
+    /// option `name`, like "notices", is translated.
+    ///
+    pub fn translates(&self, name: &str) -> bool {
+        match name {
+            "notices" => self.notices,
+            "topics"  => self.topics,
+            "parts"   => self.parts,
+            "quits"   => self.quits,
+            "kicks"   => self.kicks,
+            _         => false,
+        }
+    }
+
+    /// Gives the number of recent lines to send as context.
+    ///
+    pub fn context_lines(&self) -> usize {
+        self.context_lines.unwrap_or(DEFAULT_CONTEXT_LINES)
+    }
+}
+
+impl Config {
+    /// Loads the settings from the config file. If the file doesn't exist
+    /// yet, the default settings are returned. If it can't be read or
+    /// parsed, the error is printed and the default settings are returned.
+    ///
+    pub fn load(hc: &Hexchat) -> Self {
+        let Some(path) = config_path(hc) else {
+            return Config::default();
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                serde_json::from_str(&text).unwrap_or_else(|err| {
+                    hc.print(&format!("Translator: failed to parse {}: {}",
+                                      path.display(), err));
+                    Config::default()
+                })
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Config::default()
+            },
+            Err(err) => {
+                hc.print(&format!("Translator: failed to read {}: {}",
+                                  path.display(), err));
+                Config::default()
+            }
+        }
+    }
+
+    /// Writes the settings to the config file.
+    ///
+    pub fn save(&self, hc: &Hexchat) -> io::Result<()> {
+        let path = config_path(hc).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound,
+                           "Hexchat config directory unavailable")
+        })?;
+        let text = serde_json::to_string_pretty(self)?;
+        fs::write(path, text)
+    }
+
+    /// Returns the settings for `channel` on `network`, or the defaults if
+    /// none have been made.
+    ///
+    pub fn channel(&self, network: &str, channel: &str) -> ChannelSettings {
+        // Settings saved before keys were folded are still found.
+        self.channels.get(network)
+                     .and_then(|chans| {
+                         chans.get(&casemap::fold(network, channel))
+                              .or_else(|| chans.get(channel))
+                     })
+                     .cloned()
+                     .unwrap_or_default()
+    }
+
+    /// Gives mutable access to the settings for `channel` on `network`,
+    /// creating them if needed.
+    ///
+    pub fn channel_mut(&mut self, 
+                       network : &str, 
+                       channel : &str) 
+        -> &mut ChannelSettings 
+    {
+        let chans = self.channels.entry(network.to_string()).or_default();
+        let key   = casemap::fold(network, channel);
+        if !chans.contains_key(&key) {
+            if let Some(settings) = chans.remove(channel) {
+                chans.insert(key.clone(), settings);
+            }
+        }
+        chans.entry(key).or_default()
+    }
+
+    /// Gives the rewrite rules that apply to messages going in `direction`
+    /// in `channel`.
+    ///
+    pub fn rules_for(&self, 
+                     direction : Direction, 
+                     network   : &str, 
+                     channel   : &str) 
+        -> Vec<Rule> 
+    {
+        self.rules.iter()
+                  .filter(|r| r.applies(direction, network, channel))
+                  .cloned()
+                  .collect()
+    }
+
+    /// Returns the formality set for messages to `nick` on `network` with
+    /// `/LFORMALITY`, if any.
+    ///
+    pub fn user_formality(&self, network: &str, nick: &str) -> Option<&str> {
+        self.user_formality.get(network)?
+                           .get(&casemap::fold(network, nick))
+                           .map(String::as_str)
+    }
+
+    /// Sets the formality for messages to `nick` on `network`, or removes it
+    /// if `formality` is `None`. Returns `true` if there was one before.
+    ///
+    pub fn set_user_formality(&mut self, 
+                              network   : &str, 
+                              nick      : &str, 
+                              formality : Option<String>) 
+        -> bool
+    {
+        let users = self.user_formality.entry(network.to_string())
+                                       .or_default();
+        let old   = match formality {
+            Some(f) => users.insert(casemap::fold(network, nick), f),
+            None    => users.remove(&casemap::fold(network, nick)),
+        };
+        if users.is_empty() {
+            self.user_formality.remove(network);
+        }
+        old.is_some()
+    }
+
+    /// Returns the language set for `nick` on `network` with `/LUSER`, if
+    /// any.
+    ///
+    pub fn user_lang(&self, network: &str, nick: &str) -> Option<&str> {
+        self.user_langs.get(network)?
+                       .get(&casemap::fold(network, nick))
+                       .map(String::as_str)
+    }
+
+    /// Sets the language `nick`'s messages are translated from on
+    /// `network`.
+    ///
+    pub fn set_user_lang(&mut self, network: &str, nick: &str, lang: &str) {
+        self.user_langs.entry(network.to_string())
+                       .or_default()
+                       .insert(casemap::fold(network, nick), 
+                               lang.to_string());
+    }
+
+    /// Moves what's set for `old`, a nick on `network` that's changed to 
+    /// `new`, over to the new nick: its language, its formality, and the 
+    /// settings of its query window. Anything already set for the new nick
+    /// is kept.
+    /// # Returns
+    /// * `true` if anything was moved.
+    ///
+    pub fn rename_nick(&mut self, network: &str, old: &str, new: &str) -> bool {
+        let mut moved = false;
+        for users in [self.user_langs.get_mut(network), 
+                      self.user_formality.get_mut(network)].into_iter()
+                                                           .flatten() {
+            if let Some(value) = users.remove(&casemap::fold(network, old)) {
+                users.entry(casemap::fold(network, new))
+                     .or_insert(value);
+                moved = true;
+            }
+        }
+        if let Some(chans) = self.channels.get_mut(network) {
+            if let Some(settings) = chans.remove(&casemap::fold(network, 
+                                                                old)) {
+                chans.entry(casemap::fold(network, new))
+                     .or_insert(settings);
+                moved = true;
+            }
+        }
+        moved
+    }
+
+    /// Removes the language set for `nick` on `network`. Returns `true` if
+    /// there was one.
+    ///
+    pub fn remove_user_lang(&mut self, network: &str, nick: &str) -> bool {
+        let Some(users) = self.user_langs.get_mut(network) else {
+            return false;
+        };
+        let removed = users.remove(&casemap::fold(network, nick))
+                           .is_some();
+        if users.is_empty() {
+            self.user_langs.remove(network);
+        }
+        removed
+    }
+}
+
+/// Parses a formality level, one of `FORMALITY_LEVELS`. "default" is
+/// given as `None`.
+///
+pub fn parse_formality(value: &str) -> Result<Option<String>, String> {
+    let value = value.to_lowercase();
+    match value.as_str() {
+        "default" => Ok(None),
+        v if FORMALITY_LEVELS.contains(&v) => Ok(Some(value)),
+        _ => Err(format!("'{}' isn't a valid formality; use one of {}.", 
+                         value, FORMALITY_LEVELS.join(", "))),
+    }
+}
+
+/// Parses a number of lines, which can be at most `context::MAX_LINES`.
+///
+fn parse_count(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(n) if n <= MAX_LINES => Ok(n),
+        _ => Err(format!("'{}' isn't a valid value; use a number from 0 to \
+                          {}.", value, MAX_LINES)),
+    }
+}
+
+/// Parses an on/off option value.
+///
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "on"  | "yes" | "true"  | "1" => Ok(true),
+        "off" | "no"  | "false" | "0" => Ok(false),
+        _ => Err(format!("'{}' isn't a valid value; use on or off.", value)),
+    }
+}
+
+/// Formats an on/off option value for display.
+///
+fn on_off(value: bool) -> String {
+    if value { "on".into() } else { "off".into() }
+}
+
+/// Gives the full path to the config file.
+///
+fn config_path(hc: &Hexchat) -> Option<PathBuf> {
+    let dir = hc.get_info("configdir")?;
+    Some(PathBuf::from(dir).join(CONFIG_FILE_NAME))
+}
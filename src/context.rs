@@ -0,0 +1,78 @@
+//! Keeps the recent lines of each conversation, so they can be sent to the
+//! translation service as context for the next line. Short chat lines are
+//! often ambiguous on their own, and the lines before them help the service
+//! pick the right meaning. DeepL doesn't bill for the context it's sent.
+//!
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::casemap;
+
+/// The most lines kept for each conversation.
+///
+pub const MAX_LINES: usize = 20;
+
+/// The recent lines of each conversation, keyed by network and folded
+/// channel. See `casemap::fold()`.
+///
+#[derive(Default, Debug)]
+pub struct Conversations {
+    lines: HashMap<(String, String), VecDeque<String>>,
+}
+
+impl Conversations {
+    /// Records a line said in `channel` on `network`. Once there are
+    /// `MAX_LINES` lines, the oldest is dropped.
+    /// # Arguments
+    /// * `network` - The network of the channel.
+    /// * `channel` - The channel or query the line was said in.
+    /// * `nick`    - The nick that said it.
+    /// * `text`    - What was said, free of formatting.
+    ///
+    pub fn record(&mut self,
+                  network : &str,
+                  channel : &str,
+                  nick    : &str,
+                  text    : &str)
+    {
+        let lines = self.lines.entry((network.to_string(),
+                                      casemap::fold(network, channel)))
+                              .or_default();
+        if lines.len() == MAX_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(format!("{}: {}", nick, text));
+    }
+
+    /// Moves the lines of the query with `old`, a nick on `network` that's 
+    /// changed to `new`, over to the query with the new nick.
+    ///
+    pub fn rename(&mut self, network: &str, old: &str, new: &str) {
+        let key = (network.to_string(), casemap::fold(network, old));
+        if let Some(lines) = self.lines.remove(&key) {
+            self.lines.entry((network.to_string(), 
+                              casemap::fold(network, new)))
+                      .or_insert(lines);
+        }
+    }
+
+    /// Gives the last `count` lines said in `channel` on `network`, oldest
+    /// first and one per line, or `None` if there are none.
+    ///
+    pub fn recent(&self,
+                  network : &str,
+                  channel : &str,
+                  count   : usize)
+        -> Option<String>
+    {
+        let lines = self.lines.get(&(network.to_string(),
+                                     casemap::fold(network, channel)))?;
+        let skip  = lines.len().saturating_sub(count);
+        let text  = lines.iter()
+                         .skip(skip)
+                         .cloned()
+                         .collect::<Vec<_>>()
+                         .join("\n");
+        (!text.is_empty()).then_some(text)
+    }
+}
@@ -0,0 +1,532 @@
+//! Carries mIRC formatting - bold, italics, underline, colors and so on -
+//! through translation. Formatted text is converted to XML markup, which the
+//! translation service preserves when asked to handle XML tags, and the
+//! translated markup is converted back to mIRC formatting codes.
+//!
+//! The markup uses one element per attribute: `<b>`, `<i>`, `<u>`, `<s>`
+//! (strikethrough), `<m>` (monospace), `<r>` (reverse), and
+//! `<c fg="04" bg="01">` for colors. Text to be left untranslated is wrapped
+//! in `<keep>` elements, which the service is asked to ignore.
+//!
+
+use std::ops::Range;
+
+/// The element wrapping text the translation service is to leave as is.
+///
+pub const KEEP_TAG: &str = "keep";
+
+const BOLD      : char = '\x02';
+const COLOR     : char = '\x03';
+const HEX_COLOR : char = '\x04';
+const RESET     : char = '\x0F';
+const MONOSPACE : char = '\x11';
+const REVERSE   : char = '\x16';
+const ITALICS   : char = '\x1D';
+const STRIKE    : char = '\x1E';
+const UNDERLINE : char = '\x1F';
+
+// Mark the start and end of text to keep while the markup is built.
+const KEEP_START : char = '\u{E000}';
+const KEEP_END   : char = '\u{E001}';
+
+/// The formatting in effect for a run of text.
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Style {
+    bold      : bool,
+    italics   : bool,
+    underline : bool,
+    strike    : bool,
+    monospace : bool,
+    reverse   : bool,
+    fg        : Option<u8>,
+    bg        : Option<u8>,
+}
+
+/// Text with mIRC formatting, held as its plain text and the style of each
+/// run of it, so parts of it can be taken with their formatting intact.
+///
+#[derive(Debug, Clone, Default)]
+pub struct Styled {
+    plain : String,
+    runs  : Vec<(Range<usize>, Style)>,
+}
+
+impl Styled {
+    /// Parses text with mIRC formatting codes.
+    ///
+    pub fn new(text: &str) -> Self {
+        let mut styled = Styled::default();
+        for (style, run) in runs(text) {
+            let start = styled.plain.len();
+            styled.plain.push_str(&run);
+            styled.runs.push((start..styled.plain.len(), style));
+        }
+        styled
+    }
+
+    /// Gives the text without its formatting codes.
+    ///
+    pub fn plain(&self) -> &str {
+        &self.plain
+    }
+
+    /// Gives the byte range `range` of the plain text with the codes that
+    /// format it, as a line on its own: the style in effect at its start is
+    /// opened first, and no codes follow its last character.
+    ///
+    pub fn render(&self, range: Range<usize>) -> String {
+        let mut text    = String::with_capacity(range.len());
+        let mut current = Style::default();
+        for (run, style) in &self.runs {
+            let start = run.start.max(range.start);
+            let end   = run.end.min(range.end);
+            if start >= end {
+                continue;
+            }
+            if *style != current {
+                text.push_str(&transition(&current, style));
+                current = style.clone();
+            }
+            text.push_str(&self.plain[start..end]);
+        }
+        text
+    }
+}
+
+/// Indicates whether `text` contains any mIRC formatting codes.
+///
+pub fn has_formatting(text: &str) -> bool {
+    text.chars().any(|c| matches!(c, BOLD | COLOR | HEX_COLOR | RESET
+                                     | MONOSPACE | REVERSE | ITALICS
+                                     | STRIKE | UNDERLINE))
+}
+
+/// Gives `text` with its mIRC formatting codes removed.
+///
+pub fn strip_codes(text: &str) -> String {
+    runs(text).into_iter().map(|(_, t)| t).collect()
+}
+
+/// Converts text with mIRC formatting codes to XML markup.
+/// # Arguments
+/// * `text` - The text to convert.
+/// * `keep` - The byte ranges of `text` to wrap in `<keep>` elements, in
+///   order and without overlaps.
+///
+pub fn to_xml(text: &str, keep: &[Range<usize>]) -> String {
+    let mut marked = String::with_capacity(text.len() + keep.len() * 6);
+    let mut last   = 0;
+    let unmarked   = |s: &str| s.replace([KEEP_START, KEEP_END], "");
+    for span in keep {
+        marked.push_str(&unmarked(&text[last..span.start]));
+        marked.push(KEEP_START);
+        marked.push_str(&unmarked(&text[span.clone()]));
+        marked.push(KEEP_END);
+        last = span.end;
+    }
+    marked.push_str(&unmarked(&text[last..]));
+
+    let mut xml     = String::new();
+    let mut keeping = false;
+    for (style, run) in runs(&marked) {
+        let tags = tags(&style);
+        for tag in &tags {
+            xml.push_str(&format!("<{}>", tag));
+        }
+        // A kept span may cross a change of style, so it's closed at the end
+        // of each run and reopened at the start of the next.
+        if keeping {
+            xml.push_str(&format!("<{}>", KEEP_TAG));
+        }
+        for c in run.chars() {
+            match c {
+                KEEP_START => {
+                    keeping = true;
+                    xml.push_str(&format!("<{}>", KEEP_TAG));
+                },
+                KEEP_END => {
+                    keeping = false;
+                    xml.push_str(&format!("</{}>", KEEP_TAG));
+                },
+                c => escape_into(&mut xml, c),
+            }
+        }
+        if keeping {
+            xml.push_str(&format!("</{}>", KEEP_TAG));
+        }
+        for tag in tags.iter().rev() {
+            let name = tag.split(' ').next().unwrap_or(tag);
+            xml.push_str(&format!("</{}>", name));
+        }
+    }
+    xml
+}
+
+/// Converts XML markup produced by `to_xml()` - after translation - back to
+/// text with mIRC formatting codes. Unknown elements are dropped, keeping
+/// their text.
+///
+pub fn from_xml(xml: &str) -> String {
+    let mut text    = String::new();
+    let mut stack   = Vec::<(String, Style)>::new();
+    let mut current = Style::default();
+    let mut rest    = xml;
+
+    while !rest.is_empty() {
+        if let Some(tail) = rest.strip_prefix('<') {
+            let Some(end) = tail.find('>') else {
+                // Not a tag after all.
+                push_run(&mut text, &mut current, &stack, "<");
+                rest = tail;
+                continue;
+            };
+            let tag = &tail[..end];
+            rest    = &tail[end + 1..];
+
+            if let Some(name) = tag.strip_prefix('/') {
+                if let Some(pos) = stack.iter().rposition(|(n, _)| n == name) {
+                    stack.truncate(pos);
+                }
+            } else if !tag.ends_with('/') {
+                let base  = stack.last().map(|(_, s)| s.clone())
+                                        .unwrap_or_default();
+                let (name, style) = apply_tag(tag, base);
+                stack.push((name, style));
+            }
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let run = unescape(&rest[..end]);
+            push_run(&mut text, &mut current, &stack, &run);
+            rest = &rest[end..];
+        }
+    }
+    if current != Style::default() {
+        text.push(RESET);
+    }
+    text
+}
+
+/// Appends a run of plain text to `text`, preceded by whatever codes are
+/// needed to change the current style to the one the open elements give.
+///
+fn push_run(text    : &mut String,
+            current : &mut Style,
+            stack   : &[(String, Style)],
+            run     : &str)
+{
+    let style = stack.last().map(|(_, s)| s.clone()).unwrap_or_default();
+    if style != *current {
+        text.push_str(&transition(current, &style));
+        *current = style;
+    }
+    text.push_str(run);
+}
+
+/// Gives the mIRC codes that change the formatting from `from` to `to`.
+/// Attributes can only be toggled on and off, so if any need to be turned
+/// off, everything is reset and the ones wanted are turned back on.
+///
+fn transition(from: &Style, to: &Style) -> String {
+    let turns_off = (from.bold      && !to.bold)
+                 || (from.italics   && !to.italics)
+                 || (from.underline && !to.underline)
+                 || (from.strike    && !to.strike)
+                 || (from.monospace && !to.monospace)
+                 || (from.reverse   && !to.reverse)
+                 || (from.fg.is_some() && to.fg.is_none())
+                 || (from.bg.is_some() && to.bg.is_none());
+
+    let mut codes = String::new();
+    let base = if turns_off {
+        codes.push(RESET);
+        Style::default()
+    } else {
+        from.clone()
+    };
+    for (on, was_on, code) in [(to.bold,      base.bold,      BOLD),
+                               (to.italics,   base.italics,   ITALICS),
+                               (to.underline, base.underline, UNDERLINE),
+                               (to.strike,    base.strike,    STRIKE),
+                               (to.monospace, base.monospace, MONOSPACE),
+                               (to.reverse,   base.reverse,   REVERSE)] {
+        if on && !was_on {
+            codes.push(code);
+        }
+    }
+    if (to.fg, to.bg) != (base.fg, base.bg) {
+        if let Some(fg) = to.fg {
+            codes.push_str(&format!("{COLOR}{:02}", fg));
+            if let Some(bg) = to.bg {
+                codes.push_str(&format!(",{:02}", bg));
+            }
+        }
+    }
+    codes
+}
+
+/// Parses the contents of an opening tag and gives its name and the style
+/// it produces when applied to `base`.
+///
+fn apply_tag(tag: &str, mut style: Style) -> (String, Style) {
+    let name = tag.split_whitespace().next().unwrap_or("").to_string();
+    match name.as_str() {
+        "b" => style.bold      = true,
+        "i" => style.italics   = true,
+        "u" => style.underline = true,
+        "s" => style.strike    = true,
+        "m" => style.monospace = true,
+        "r" => style.reverse   = true,
+        "c" => {
+            style.fg = attribute(tag, "fg").and_then(|v| v.parse().ok());
+            style.bg = attribute(tag, "bg").and_then(|v| v.parse().ok());
+        },
+        _ => {}
+    }
+    (name, style)
+}
+
+/// Gives the value of the attribute `name` in the contents of a tag.
+///
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!("{}=\"", name))? + name.len() + 2;
+    let len   = tag[start..].find('"')?;
+    Some(&tag[start..start + len])
+}
+
+/// Gives the elements, as the contents of their opening tags, that express
+/// `style`.
+///
+fn tags(style: &Style) -> Vec<String> {
+    let mut tags = vec![];
+    for (on, name) in [(style.bold,      "b"),
+                       (style.italics,   "i"),
+                       (style.underline, "u"),
+                       (style.strike,    "s"),
+                       (style.monospace, "m"),
+                       (style.reverse,   "r")] {
+        if on {
+            tags.push(name.to_string());
+        }
+    }
+    match (style.fg, style.bg) {
+        (Some(fg), Some(bg)) => tags.push(format!("c fg=\"{:02}\" bg=\"{:02}\"",
+                                                  fg, bg)),
+        (Some(fg), None)     => tags.push(format!("c fg=\"{:02}\"", fg)),
+        _                    => {}
+    }
+    tags
+}
+
+/// Breaks text with mIRC formatting codes into runs of plain text paired
+/// with the style they're displayed in. Hex colors (`\x04`) aren't
+/// supported and are dropped.
+///
+fn runs(text: &str) -> Vec<(Style, String)> {
+    let mut runs  = vec![];
+    let mut style = Style::default();
+    let mut run   = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let prev = style.clone();
+        match c {
+            BOLD      => style.bold      = !style.bold,
+            ITALICS   => style.italics   = !style.italics,
+            UNDERLINE => style.underline = !style.underline,
+            STRIKE    => style.strike    = !style.strike,
+            MONOSPACE => style.monospace = !style.monospace,
+            REVERSE   => style.reverse   = !style.reverse,
+            RESET     => style = Style::default(),
+            COLOR     => {
+                let fg = take_digits(&mut chars, 2, |c| c.is_ascii_digit());
+                if fg.is_empty() {
+                    style.fg = None;
+                    style.bg = None;
+                } else {
+                    style.fg = fg.parse().ok();
+                    if chars.peek() == Some(&',') {
+                        let mut ahead = chars.clone();
+                        ahead.next();
+                        if ahead.peek().is_some_and(|c| c.is_ascii_digit()) {
+                            chars.next();
+                            let bg = take_digits(&mut chars, 2,
+                                                 |c| c.is_ascii_digit());
+                            style.bg = bg.parse().ok();
+                        }
+                    }
+                }
+            },
+            HEX_COLOR => {
+                take_digits(&mut chars, 6, |c| c.is_ascii_hexdigit());
+                if chars.peek() == Some(&',') {
+                    let mut ahead = chars.clone();
+                    ahead.next();
+                    if ahead.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+                        chars.next();
+                        take_digits(&mut chars, 6, |c| c.is_ascii_hexdigit());
+                    }
+                }
+            },
+            c => {
+                run.push(c);
+                continue;
+            }
+        }
+        if style != prev && !run.is_empty() {
+            runs.push((prev, std::mem::take(&mut run)));
+        }
+    }
+    if !run.is_empty() {
+        runs.push((style, run));
+    }
+    runs
+}
+
+/// Takes up to `max` characters matching `pred` from the iterator.
+///
+fn take_digits<I>(chars : &mut std::iter::Peekable<I>,
+                  max   : usize,
+                  pred  : fn(&char) -> bool)
+    -> String
+where
+    I: Iterator<Item = char>,
+{
+    let mut digits = String::new();
+    while digits.len() < max {
+        match chars.peek() {
+            Some(c) if pred(c) => digits.push(*c),
+            _ => break,
+        }
+        chars.next();
+    }
+    digits
+}
+
+/// Appends `c` to `xml`, escaped if it's an XML special character.
+///
+fn escape_into(xml: &mut String, c: char) {
+    match c {
+        '<' => xml.push_str("&lt;"),
+        '>' => xml.push_str("&gt;"),
+        '&' => xml.push_str("&amp;"),
+        c   => xml.push(c),
+    }
+}
+
+/// Replaces XML entities in `text` with the characters they stand for.
+///
+fn unescape(text: &str) -> String {
+    let mut out  = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('&') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        let entity = rest.find(';').map(|end| (&rest[1..end], end));
+        let ch = entity.and_then(|(name, _)| match name {
+            "lt"   => Some('<'),
+            "gt"   => Some('>'),
+            "amp"  => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => {
+                let num = name.strip_prefix('#')?;
+                let code = match num.strip_prefix('x') {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None      => num.parse().ok()?,
+                };
+                char::from_u32(code)
+            }
+        });
+        match (ch, entity) {
+            (Some(ch), Some((_, end))) => {
+                out.push(ch);
+                rest = &rest[end + 1..];
+            },
+            _ => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Converts to markup and back, as a translation into the same language
+    /// would.
+    fn round_trip(text: &str) -> String {
+        from_xml(&to_xml(text, &[]))
+    }
+
+    #[test]
+    fn formatting_becomes_markup() {
+        assert_eq!(to_xml("\x02bold\x02 and \x0304,01red\x03", &[]),
+                   "<b>bold</b> and <c fg=\"04\" bg=\"01\">red</c>");
+        assert_eq!(to_xml("\x1D\x1Fboth", &[]), "<i><u>both</u></i>");
+    }
+
+    #[test]
+    fn markup_round_trips() {
+        for text in ["plain text",
+                     "\x02bold\x02 plain",
+                     "\x0304red \x02bold red\x0F plain",
+                     "\x1Ditalic\x1D \x1Funder\x1F \x16rev\x16 \x11mono\x11"] {
+            let xml = to_xml(text, &[]);
+            assert_eq!(strip_codes(&round_trip(text)), strip_codes(text));
+            assert_eq!(to_xml(&round_trip(text), &[]), xml, "{:?}", text);
+        }
+        assert_eq!(round_trip("\x02bold\x02 plain"), "\x02bold\x0F plain");
+    }
+
+    #[test]
+    fn special_characters_are_escaped_and_restored() {
+        let text = "a < b && c > d";
+        assert_eq!(to_xml(text, &[]), "a &lt; b &amp;&amp; c &gt; d");
+        assert_eq!(round_trip(text), text);
+    }
+
+    #[test]
+    fn entities_are_unescaped() {
+        assert_eq!(from_xml("one&#10;two"), "one\ntwo");
+        assert_eq!(from_xml("&#x41;&#66;&quot;&apos;"), "AB\"'");
+        assert_eq!(from_xml("fish &amp; chips &unknown; &#xZZ;"),
+                   "fish & chips &unknown; &#xZZ;");
+    }
+
+    #[test]
+    fn kept_spans_are_wrapped() {
+        let text = "see https://x.org now";
+        let url  = 4..17;
+        let xml  = to_xml(text, &[url]);
+        assert_eq!(xml, "see <keep>https://x.org</keep> now");
+        assert_eq!(from_xml(&xml), text);
+    }
+
+    #[test]
+    fn kept_span_crossing_a_style_change_is_reopened() {
+        let text = "a\x02bc\x02d";
+        // The span covers "bc" and "d".
+        let span = 2..6;
+        let xml  = to_xml(text, &[span]);
+        assert_eq!(xml, "a<b><keep>bc</keep></b><keep>d</keep>");
+    }
+
+    #[test]
+    fn unknown_elements_keep_their_text() {
+        assert_eq!(from_xml("<x>hi</x> <b>there</b>"), "hi \x02there\x0F");
+    }
+
+    #[test]
+    fn styled_renders_ranges_with_their_style() {
+        let styled = Styled::new("\x02one two\x02 three");
+        assert_eq!(styled.plain(), "one two three");
+        assert_eq!(styled.render(4..7), "\x02two");
+        assert_eq!(styled.render(8..13), "three");
+        assert_eq!(styled.render(0..13), "\x02one two\x0F three");
+    }
+}
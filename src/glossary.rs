@@ -0,0 +1,438 @@
+//! Manages DeepL glossaries, which fix how particular terms - product names,
+//! technical terms and the like - are translated between a pair of
+//! languages. Glossaries are created from entries given on the command line
+//! or read from a TSV or CSV file, and attached to channels with
+//! `/LGLOSSARY USE` so their translations use them.
+//!
+//! Glossaries can also be kept locally, as files in the same format, and
+//! enforced by the plugin itself for language pairs without a DeepL
+//! glossary: each term is replaced with its preferred translation before
+//! the text is translated, and that translation is kept as it is.
+//!
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::{get_deepl_api_key, TRANSLATION_SERVER_TIMEOUT};
+
+/// The URL of DeepL's glossary API.
+///
+const DEEPL_GLOSSARY_URL: &str = "https://api-free.deepl.com/v2/glossaries";
+
+/// The name of the directory within Hexchat's config directory holding the
+/// local glossaries. Each is named for its language pair, e.g. "en-de.tsv".
+///
+pub const LOCAL_GLOSSARY_DIR: &str = "addon_translator_glossaries";
+
+/// A glossary as described by DeepL.
+///
+#[derive(Deserialize, Clone, Debug)]
+pub struct Glossary {
+    pub glossary_id : String,
+    pub name        : String,
+    pub source_lang : String,
+    pub target_lang : String,
+    #[serde(default)]
+    pub entry_count : u64,
+}
+
+/// The request to create a glossary.
+///
+#[derive(Serialize)]
+struct CreateRequest<'a> {
+    name           : &'a str,
+    source_lang    : &'a str,
+    target_lang    : &'a str,
+    entries        : String,
+    entries_format : &'a str,
+}
+
+/// The response to listing the glossaries.
+///
+#[derive(Deserialize)]
+struct ListResponse {
+    glossaries: Vec<Glossary>,
+}
+
+/// Gives the key under which a glossary for translating from `source` to
+/// `target` is attached to a channel.
+///
+pub fn pair_key(source: &str, target: &str) -> String {
+    format!("{}>{}", source.to_lowercase(), target.to_lowercase())
+}
+
+/// Parses glossary entries, one per line, with the term and its translation
+/// separated by a tab (TSV) or, on lines without a tab, a comma (CSV).
+/// CSV fields can be quoted, with `""` for a quote within them, so terms
+/// can hold commas. Blank lines and lines starting with '#' are ignored.
+/// # Returns
+/// * `Err()` describing the first line that isn't a valid entry.
+///
+pub fn parse_entries(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut entries = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = match line.split_once('\t') {
+            Some((term, trans)) => Some(vec![term.to_string(), 
+                                             trans.to_string()]),
+            None                => split_csv(line),
+        };
+        match fields.as_deref() {
+            Some([term, trans]) if !term.trim().is_empty()
+                                && !trans.trim().is_empty() => {
+                entries.push((term.trim().to_string(),
+                              trans.trim().to_string()));
+            },
+            _ => {
+                return Err(format!("Line {} isn't a valid entry: {}",
+                                   n + 1, line));
+            }
+        }
+    }
+    if entries.is_empty() {
+        return Err("There are no entries.".to_string());
+    }
+    Ok(entries)
+}
+
+/// Splits a line of CSV into its fields, unquoting quoted ones.
+/// # Returns
+/// * `None` if a quoted field isn't closed, or is followed by anything but
+///   a comma.
+///
+fn split_csv(line: &str) -> Option<Vec<String>> {
+    let mut fields = vec![];
+    let mut chars  = line.chars().peekable();
+    loop {
+        let mut field = String::new();
+        while chars.peek() == Some(&' ') {
+            chars.next();
+        }
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next()? {
+                    '"' if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    },
+                    '"' => break,
+                    c   => field.push(c),
+                }
+            }
+            while chars.peek() == Some(&' ') {
+                chars.next();
+            }
+            if !matches!(chars.peek(), Some(',') | None) {
+                return None;
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c == ',' {
+                    break;
+                }
+                field.push(c);
+                chars.next();
+            }
+        }
+        fields.push(field);
+        if chars.next().is_none() {
+            return Some(fields);
+        }
+    }
+}
+
+/// Reads and parses the glossary entries in a TSV or CSV file. See
+/// `parse_entries()`.
+///
+pub fn read_entries(path: &str) -> Result<Vec<(String, String)>, String> {
+    let text = fs::read_to_string(path).map_err(|err| {
+        format!("Failed to read {}: {}", path, err)
+    })?;
+    parse_entries(&text)
+}
+
+/// Gives the path of the local glossary file for translating from `source`
+/// to `target`.
+///
+pub fn local_path(dir: &Path, source: &str, target: &str) -> PathBuf {
+    dir.join(format!("{}-{}.tsv", source.to_lowercase(), 
+                                  target.to_lowercase()))
+}
+
+/// Reads the local glossary for translating from `source` to `target`. If
+/// there isn't one, or it can't be read, there are no entries.
+///
+pub fn local_entries(dir    : &Path, 
+                     source : &str, 
+                     target : &str) 
+    -> Vec<(String, String)> 
+{
+    let path = local_path(dir, source, target);
+    fs::read_to_string(path).ok()
+                            .and_then(|text| parse_entries(&text).ok())
+                            .unwrap_or_default()
+}
+
+/// Lists the language pairs, like "en-de", that have a local glossary, 
+/// with the number of entries in each.
+///
+pub fn local_glossaries(dir: &Path) -> Vec<(String, usize)> {
+    let Ok(files) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut pairs = files.filter_map(|f| f.ok())
+                         .filter_map(|f| {
+                             let path = f.path();
+                             let pair = path.file_name()?
+                                            .to_str()?
+                                            .strip_suffix(".tsv")?
+                                            .to_string();
+                             let text = fs::read_to_string(&path).ok()?;
+                             let n    = parse_entries(&text).map_or(0, |e| {
+                                            e.len()
+                                        });
+                             Some((pair, n))
+                         })
+                         .collect::<Vec<_>>();
+    pairs.sort();
+    pairs
+}
+
+/// Replaces the terms of a local glossary found in `text` with their
+/// preferred translations. Terms are matched as whole words, ignoring
+/// case, with longer terms taking precedence.
+/// # Returns
+/// * The text with the terms replaced, and the byte ranges of the
+///   replacements, which are to be kept untranslated.
+///
+pub fn enforce(text    : &str, 
+               entries : &[(String, String)]) 
+    -> (String, Vec<Range<usize>>) 
+{
+    if entries.is_empty() {
+        return (text.to_string(), vec![]);
+    }
+    let mut terms = entries.iter().map(|(t, _)| t).collect::<Vec<_>>();
+    terms.sort_by_key(|t| std::cmp::Reverse(t.len()));
+    // A term is bounded as a word only on the sides where it starts or ends
+    // with a word character, so terms like "C++" and ".NET" match too.
+    let is_word = |c: Option<char>| {
+        c.is_some_and(|c| c.is_alphanumeric() || c == '_')
+    };
+    let pattern = terms.iter()
+                       .map(|t| {
+                           let start = if is_word(t.chars().next())
+                                       { r"\b" } else { "" };
+                           let end   = if is_word(t.chars().next_back())
+                                       { r"\b" } else { "" };
+                           format!("{}{}{}", start, regex::escape(t), end)
+                       })
+                       .collect::<Vec<_>>()
+                       .join("|");
+    let Ok(regex) = Regex::new(&format!(r"(?i)(?:{})", pattern)) else {
+        return (text.to_string(), vec![]);
+    };
+    let trans = entries.iter()
+                       .map(|(t, tr)| (t.to_lowercase(), tr))
+                       .collect::<HashMap<_, _>>();
+
+    let mut out   = String::with_capacity(text.len());
+    let mut spans = vec![];
+    let mut last  = 0;
+    for m in regex.find_iter(text) {
+        let Some(tr) = trans.get(&m.as_str().to_lowercase()) else {
+            continue;
+        };
+        out.push_str(&text[last..m.start()]);
+        spans.push(out.len()..out.len() + tr.len());
+        out.push_str(tr);
+        last = m.end();
+    }
+    out.push_str(&text[last..]);
+    (out, spans)
+}
+
+/// Creates a glossary on the DeepL account.
+/// # Arguments
+/// * `name`    - The name to give the glossary.
+/// * `source`  - The language of the terms.
+/// * `target`  - The language of their translations.
+/// * `entries` - The terms paired with their translations.
+///
+pub fn create(name    : &str,
+              source  : &str,
+              target  : &str,
+              entries : &[(String, String)])
+    -> Result<Glossary, String>
+{
+    let entries = entries.iter()
+                         .map(|(term, trans)| format!("{}\t{}", term, trans))
+                         .collect::<Vec<_>>()
+                         .join("\n");
+    let request = CreateRequest { name,
+                                  source_lang    : &source.to_lowercase(),
+                                  target_lang    : &target.to_lowercase(),
+                                  entries,
+                                  entries_format : "tsv" };
+    let response = authorized(agent().post(DEEPL_GLOSSARY_URL))?
+                       .send_json(&request)
+                       .map_err(describe)?;
+    response.into_json::<Glossary>()
+            .map_err(|err| format!("Failed to parse DeepL response: {}", err))
+}
+
+/// Lists the glossaries on the DeepL account.
+///
+pub fn list() -> Result<Vec<Glossary>, String> {
+    let response = authorized(agent().get(DEEPL_GLOSSARY_URL))?
+                       .call()
+                       .map_err(describe)?;
+    response.into_json::<ListResponse>()
+            .map(|list| list.glossaries)
+            .map_err(|err| format!("Failed to parse DeepL response: {}", err))
+}
+
+/// Gives the details of the glossary with the ID `id`.
+///
+pub fn get(id: &str) -> Result<Glossary, String> {
+    let url      = format!("{}/{}", DEEPL_GLOSSARY_URL, id);
+    let response = authorized(agent().get(&url))?
+                       .call()
+                       .map_err(describe)?;
+    response.into_json::<Glossary>()
+            .map_err(|err| format!("Failed to parse DeepL response: {}", err))
+}
+
+/// Deletes the glossary with the ID `id` from the DeepL account.
+///
+pub fn delete(id: &str) -> Result<(), String> {
+    let url = format!("{}/{}", DEEPL_GLOSSARY_URL, id);
+    authorized(agent().delete(&url))?
+        .call()
+        .map_err(describe)?;
+    Ok(())
+}
+
+/// Creates the HTTP agent requests are made with.
+///
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+         .timeout_read(Duration::from_secs(TRANSLATION_SERVER_TIMEOUT))
+         .build()
+}
+
+/// Adds the DeepL authorization header to a request.
+///
+fn authorized(request: ureq::Request) -> Result<ureq::Request, String> {
+    let api_key = get_deepl_api_key().ok_or_else(|| {
+        "DeepL API key not found. Set DEEPL_API_KEY environment variable."
+            .to_string()
+    })?;
+    Ok(request.set("Authorization", &format!("DeepL-Auth-Key {}", api_key)))
+}
+
+/// Describes a failed request to the glossary API.
+///
+fn describe(err: ureq::Error) -> String {
+    format!("DeepL glossary request failed: {}", err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter()
+             .map(|(t, tr)| (t.to_string(), tr.to_string()))
+             .collect()
+    }
+
+    /// The parts of `text` covered by `spans`.
+    fn kept<'a>(text: &'a str, spans: &[Range<usize>]) -> Vec<&'a str> {
+        spans.iter().map(|r| &text[r.clone()]).collect()
+    }
+
+    #[test]
+    fn enforce_replaces_whole_words_ignoring_case() {
+        let entries = entries(&[("server", "Server"), ("bug", "Fehler")]);
+        let (out, spans) = enforce("The SERVER has a bug, debug it", 
+                                   &entries);
+        assert_eq!(out, "The Server has a Fehler, debug it");
+        assert_eq!(kept(&out, &spans), ["Server", "Fehler"]);
+    }
+
+    #[test]
+    fn enforce_prefers_longer_terms() {
+        let entries = entries(&[("pull", "ziehen"),
+                                ("pull request", "Pull-Request")]);
+        let (out, spans) = enforce("open a pull request", &entries);
+        assert_eq!(out, "open a Pull-Request");
+        assert_eq!(kept(&out, &spans), ["Pull-Request"]);
+    }
+
+    #[test]
+    fn enforce_matches_terms_with_non_word_edges() {
+        let entries = entries(&[("C++", "C++"), (".NET", ".NET-Plattform"),
+                                ("Node.js!", "Node.js!")]);
+        let (out, spans) = enforce("Use c++ or .net, or node.js! now", 
+                                   &entries);
+        assert_eq!(out, "Use C++ or .NET-Plattform, or Node.js! now");
+        assert_eq!(kept(&out, &spans), ["C++", ".NET-Plattform", "Node.js!"]);
+    }
+
+    #[test]
+    fn enforce_keeps_word_edges_bounded() {
+        let entries = entries(&[("C++", "C++")]);
+        let (out, spans) = enforce("ABC++ and C++", &entries);
+        assert_eq!(out, "ABC++ and C++");
+        let last = 10..13;
+        assert_eq!(spans, [last]);
+    }
+
+    #[test]
+    fn enforce_without_entries_changes_nothing() {
+        let (out, spans) = enforce("text", &[]);
+        assert_eq!(out, "text");
+        assert!(spans.is_empty());
+    }
+
+    #[test]
+    fn parses_tsv_and_csv() {
+        let text = "server\tServer\n\
+                    bug, Fehler\n\
+                    \"Hello, world\",\"Hallo, \"\"Welt\"\"\"\r\n";
+        assert_eq!(parse_entries(text).unwrap(),
+                   entries(&[("server", "Server"), ("bug", "Fehler"),
+                             ("Hello, world", "Hallo, \"Welt\"")]));
+    }
+
+    #[test]
+    fn tabs_take_precedence_over_commas() {
+        assert_eq!(parse_entries("a, b\tc, d").unwrap(),
+                   entries(&[("a, b", "c, d")]));
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let text = "# terms\n\n   \nbug\tFehler\n";
+        assert_eq!(parse_entries(text).unwrap(), entries(&[("bug", "Fehler")]));
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        assert!(parse_entries("bug\tFehler\njust a term").is_err());
+        assert!(parse_entries("a,b,c").is_err());
+        assert!(parse_entries("\"unclosed,b").is_err());
+        assert!(parse_entries("bug\t ").is_err());
+        assert!(parse_entries("# only a comment").is_err());
+    }
+}
@@ -0,0 +1,290 @@
+//! Offline language identification. Chat lines are classified without any
+//! network call, first by the Unicode script of their letters, and then - for
+//! scripts shared by several languages (Latin and Cyrillic) - by a naive
+//! Bayes classifier over character n-grams. The n-gram profiles are trained
+//! from the sample text in `langid/corpus.txt`, which is embedded in the
+//! plugin binary, the first time a detection is requested.
+//!
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// The training text for the n-gram profiles. Sections start with a line
+/// holding the language code in brackets, e.g. `[de]`.
+///
+const CORPUS: &str = include_str!("langid/corpus.txt");
+
+/// The longest character n-gram collected from the text. All n-grams from
+/// length 1 up to this are used.
+///
+const MAX_NGRAM: usize = 3;
+
+/// Scales the per-n-gram average log-likelihoods before they're turned into
+/// probabilities. Higher values make the confidence scores more decisive.
+///
+const CONFIDENCE_SCALE: f64 = 2.0;
+
+/// The result of a detection: the language code (as used in
+/// `SUPPORTED_LANGUAGES`) and a confidence score from 0.0 to 1.0.
+///
+#[derive(Debug, Clone)]
+pub struct Detection {
+    pub lang       : &'static str,
+    pub confidence : f64,
+}
+
+/// The writing systems the detector distinguishes. Several of them map
+/// directly to a single supported language.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Script {
+    Latin, Cyrillic, Greek, Arabic, Devanagari, Hangul, Kana, Han,
+}
+
+/// A trained n-gram profile for one language.
+///
+struct Profile {
+    lang    : &'static str,
+    script  : Script,
+    logp    : HashMap<String, f64>,
+    unknown : f64,
+}
+
+/// Detects the language of `text`. Returns `None` if the text has too few
+/// letters to classify, or is written in a script none of the profiles
+/// cover.
+/// # Arguments
+/// * `text` - The text to classify. It should be free of IRC formatting.
+/// # Returns
+/// * The most likely language and the detector's confidence in it.
+///
+pub fn detect(text: &str) -> Option<Detection> {
+    detect_all(text).into_iter().next()
+}
+
+/// Like `detect()`, but returns every candidate language for the text's
+/// script, ordered from most to least likely. The confidence scores of the
+/// candidates add up to 1.0.
+///
+pub fn detect_all(text: &str) -> Vec<Detection> {
+    let mut counts = HashMap::new();
+    let mut total  = 0;
+
+    for c in text.chars().filter(|c| c.is_alphabetic()) {
+        if let Some(script) = script_of(c) {
+            *counts.entry(script).or_insert(0) += 1;
+        }
+        total += 1;
+    }
+    let Some((&script, &n)) = counts.iter().max_by_key(|(_, n)| **n) else {
+        return vec![];
+    };
+    if total < 2 {
+        return vec![];
+    }
+    let share = n as f64 / total as f64;
+
+    match script {
+        Script::Greek      => vec![Detection { lang: "el", confidence: share }],
+        Script::Arabic     => vec![Detection { lang: "ar", confidence: share }],
+        Script::Devanagari => vec![Detection { lang: "hi", confidence: share }],
+        Script::Hangul     => vec![Detection { lang: "ko", confidence: share }],
+        Script::Kana       => vec![Detection { lang: "ja", confidence: 1.0   }],
+        Script::Han => {
+            // Japanese text mixes kanji with kana; Chinese has no kana.
+            let kana = counts.get(&Script::Kana).copied().unwrap_or(0);
+            if kana > 0 {
+                vec![Detection { lang: "ja", confidence: 1.0 }]
+            } else {
+                vec![Detection { lang: "zh", confidence: share }]
+            }
+        },
+        Script::Latin | Script::Cyrillic => classify(text, script, share),
+    }
+}
+
+/// Runs the naive Bayes classifier over the profiles for `script`.
+/// # Arguments
+/// * `text`    - The text to classify.
+/// * `script`  - The dominant script of the text's letters.
+/// * `share`   - The fraction of the text's letters written in `script`. The
+///   confidence scores are scaled by it.
+///
+fn classify(text: &str, script: Script, share: f64) -> Vec<Detection> {
+    let ngrams = ngrams(text);
+    if ngrams.is_empty() {
+        return vec![];
+    }
+    let n = ngrams.len() as f64;
+
+    let mut scores = profiles().iter()
+                               .filter(|p| p.script == script)
+                               .map(|p| {
+                                   let ll: f64 = ngrams.iter()
+                                       .map(|g| p.logp.get(g)
+                                                      .copied()
+                                                      .unwrap_or(p.unknown))
+                                       .sum();
+                                   (p.lang, ll / n)
+                               })
+                               .collect::<Vec<_>>();
+
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    // Softmax over the average log-likelihoods, sharpened by the scale and
+    // by how much text there is to go on.
+    let Some(&(_, best)) = scores.first() else { return vec![] };
+    let weight  = CONFIDENCE_SCALE * n.sqrt();
+    let exps    = scores.iter()
+                        .map(|(_, s)| ((s - best) * weight).exp())
+                        .collect::<Vec<_>>();
+    let sum     = exps.iter().sum::<f64>();
+
+    scores.iter()
+          .zip(exps)
+          .map(|(&(lang, _), e)| Detection { lang,
+                                             confidence: e / sum * share })
+          .collect()
+}
+
+/// Returns the trained profiles, training them on first use.
+///
+fn profiles() -> &'static [Profile] {
+    static PROFILES: OnceLock<Vec<Profile>> = OnceLock::new();
+    PROFILES.get_or_init(train)
+}
+
+/// Builds a profile for each language section in the embedded corpus.
+///
+fn train() -> Vec<Profile> {
+    let mut sections: Vec<(&'static str, String)> = vec![];
+
+    for line in CORPUS.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(lang) = line.strip_prefix('[')
+                                .and_then(|l| l.strip_suffix(']')) {
+            sections.push((lang, String::new()));
+        } else if let Some((_, text)) = sections.last_mut() {
+            text.push_str(line);
+            text.push(' ');
+        }
+    }
+    sections.into_iter().filter_map(|(lang, text)| {
+        let script = text.chars().find_map(script_of)?;
+        let mut counts = HashMap::<String, u32>::new();
+        for g in ngrams(&text) {
+            *counts.entry(g).or_insert(0) += 1;
+        }
+        // Add-one smoothing; unseen n-grams get the probability of a
+        // single occurrence.
+        let total   = counts.values().sum::<u32>() as f64;
+        let denom   = total + counts.len() as f64 + 1.0;
+        let logp    = counts.into_iter()
+                            .map(|(g, c)| (g, ((c as f64 + 1.0) / denom).ln()))
+                            .collect();
+        Some(Profile { lang, script, logp, unknown: (1.0 / denom).ln() })
+    }).collect()
+}
+
+/// Breaks the text into lowercase words and collects the character n-grams
+/// of each, with the words padded by a space on either side so word
+/// beginnings and endings are captured.
+///
+fn ngrams(text: &str) -> Vec<String> {
+    let mut grams = vec![];
+    let lower = text.to_lowercase();
+
+    for word in lower.split(|c: char| !c.is_alphabetic())
+                     .filter(|w| !w.is_empty()) {
+        let chars = std::iter::once(' ')
+                              .chain(word.chars())
+                              .chain(std::iter::once(' '))
+                              .collect::<Vec<_>>();
+        for len in 1..=MAX_NGRAM {
+            for win in chars.windows(len) {
+                if len == 1 && win[0] == ' ' {
+                    continue;
+                }
+                grams.push(win.iter().collect());
+            }
+        }
+    }
+    grams
+}
+
+/// Gives the script a letter belongs to, or `None` for scripts the detector
+/// doesn't know.
+///
+fn script_of(c: char) -> Option<Script> {
+    match c as u32 {
+        0x0041..=0x024F | 0x1E00..=0x1EFF => Some(Script::Latin),
+        0x0370..=0x03FF | 0x1F00..=0x1FFF => Some(Script::Greek),
+        0x0400..=0x052F                   => Some(Script::Cyrillic),
+        0x0600..=0x06FF | 0x0750..=0x077F => Some(Script::Arabic),
+        0x0900..=0x097F                   => Some(Script::Devanagari),
+        0x1100..=0x11FF | 0x3130..=0x318F
+                        | 0xAC00..=0xD7AF => Some(Script::Hangul),
+        0x3040..=0x30FF                   => Some(Script::Kana),
+        0x4E00..=0x9FFF | 0x3400..=0x4DBF => Some(Script::Han),
+        _                                 => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LOCAL_DETECT_MIN_CONFIDENCE;
+
+    fn lang(text: &str) -> Option<&'static str> {
+        detect(text).map(|d| d.lang)
+    }
+
+    #[test]
+    fn greek_is_told_by_its_script() {
+        let det = detect("Καλημέρα σε όλους").unwrap();
+        assert_eq!(det.lang, "el");
+        assert_eq!(det.confidence, 1.0);
+    }
+
+    #[test]
+    fn kana_makes_han_japanese() {
+        assert_eq!(lang("今日は雨が降っています"), Some("ja"));
+        assert_eq!(lang("ひらがなだけ"), Some("ja"));
+        assert_eq!(lang("我们明天见面吧"), Some("zh"));
+    }
+
+    #[test]
+    fn czech_and_slovak_are_told_apart() {
+        assert_eq!(lang("Děkuji moc, zítra to určitě zkusím znovu a \
+                         napíšu vám, jestli to funguje."), Some("cs"));
+        assert_eq!(lang("Ďakujem veľmi pekne, zajtra to určite skúsim \
+                         znova a napíšem vám, či to funguje."), Some("sk"));
+    }
+
+    #[test]
+    fn russian_and_ukrainian_are_told_apart() {
+        assert_eq!(lang("Спасибо большое, завтра я обязательно попробую \
+                         ещё раз и напишу, работает ли это."), Some("ru"));
+        assert_eq!(lang("Дякую дуже, завтра я обов'язково спробую ще раз \
+                         і напишу, чи це працює."), Some("uk"));
+    }
+
+    #[test]
+    fn danish_and_norwegian_are_told_apart() {
+        assert_eq!(lang("Tusind tak, jeg prøver igen i morgen og skriver \
+                         til jer, om det virker nu."), Some("da"));
+        assert_eq!(lang("Tusen takk, jeg prøver igjen i morgen og skriver \
+                         til dere om det fungerer nå."), Some("nb"));
+    }
+
+    #[test]
+    fn short_chat_words_are_not_trusted() {
+        for text in ["ok", "lol", "k"] {
+            let confidence = detect(text).map_or(0.0, |d| d.confidence);
+            assert!(confidence < LOCAL_DETECT_MIN_CONFIDENCE,
+                    "{:?} was detected with {}", text, confidence);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Learns which language each nick writes in. Every time the language of a
+//! nick's message is identified - locally or by the translation service - it
+//! is recorded as an observation. Older observations decay so the record
+//! follows a nick who switches languages, and a nick's language is only
+//! considered known once enough observations agree on it.
+//!
+
+use std::collections::HashMap;
+
+use crate::casemap;
+
+/// The factor each language's weight for a nick is multiplied by whenever a
+/// new observation is recorded for them.
+///
+const DECAY: f64 = 0.9;
+
+/// The number of observations needed before a nick's language is used.
+///
+const MIN_SAMPLES: u32 = 3;
+
+/// The share of a nick's (decayed) observation weight their most common
+/// language must have before it's used.
+///
+const MIN_SHARE: f64 = 0.6;
+
+/// The observations recorded for a single nick.
+///
+#[derive(Default, Debug)]
+struct NickStats {
+    weights : HashMap<String, f64>,
+    samples : u32,
+}
+
+/// A summary of what's been learned about a nick, as listed by `/LWHO`.
+///
+#[derive(Debug, Clone)]
+pub struct Learned {
+    pub nick    : String,
+    pub lang    : String,
+    pub share   : f64,
+    pub samples : u32,
+}
+
+impl Learned {
+    /// Indicates whether there are enough observations, and they agree
+    /// enough, for the language to be used when translating.
+    ///
+    pub fn is_confident(&self) -> bool {
+        self.samples >= MIN_SAMPLES && self.share >= MIN_SHARE
+    }
+}
+
+/// The languages learned for nicks, keyed by network and then by folded
+/// nick. See `casemap::fold()`.
+///
+#[derive(Default, Debug)]
+pub struct NickLangs {
+    networks: HashMap<String, HashMap<String, NickStats>>,
+}
+
+impl NickLangs {
+    /// Records that a message from `nick` was identified as being in `lang`.
+    /// # Arguments
+    /// * `network` - The network the message was received on.
+    /// * `nick`    - The nick that sent it.
+    /// * `lang`    - The language code identified for the message.
+    /// * `weight`  - How much the observation counts, normally the
+    ///   confidence of the identification.
+    ///
+    pub fn observe(&mut self,
+                   network : &str,
+                   nick    : &str,
+                   lang    : &str,
+                   weight  : f64)
+    {
+        let stats = self.networks.entry(network.to_string())
+                                 .or_default()
+                                 .entry(casemap::fold(network, nick))
+                                 .or_default();
+        for w in stats.weights.values_mut() {
+            *w *= DECAY;
+        }
+        *stats.weights.entry(lang.to_lowercase()).or_insert(0.0) += weight;
+        stats.samples = stats.samples.saturating_add(1);
+    }
+
+    /// Gives the language `nick` usually writes in, if it's been learned
+    /// with enough confidence to be used.
+    ///
+    pub fn language(&self, network: &str, nick: &str) -> Option<String> {
+        self.learned(network, nick)
+            .filter(Learned::is_confident)
+            .map(|l| l.lang)
+    }
+
+    /// Gives what's been learned about `nick`, whether or not it's
+    /// confident.
+    ///
+    pub fn learned(&self, network: &str, nick: &str) -> Option<Learned> {
+        let nick  = casemap::fold(network, nick);
+        let stats = self.networks.get(network)?.get(&nick)?;
+        summarize(&nick, stats)
+    }
+
+    /// Gives what's been learned about every nick on `network`, sorted by
+    /// nick.
+    ///
+    pub fn all_learned(&self, network: &str) -> Vec<Learned> {
+        let mut all = self.networks
+                          .get(network)
+                          .map(|nicks| {
+                              nicks.iter()
+                                   .filter_map(|(n, s)| summarize(n, s))
+                                   .collect::<Vec<_>>()
+                          })
+                          .unwrap_or_default();
+        all.sort_by(|a, b| a.nick.cmp(&b.nick));
+        all
+    }
+
+    /// Moves what's been learned about `old`, a nick on `network` that's
+    /// changed to `new`, over to the new nick, unless something has already
+    /// been learned about it.
+    ///
+    pub fn rename(&mut self, network: &str, old: &str, new: &str) {
+        if let Some(nicks) = self.networks.get_mut(network) {
+            if let Some(stats) = nicks.remove(&casemap::fold(network, old)) {
+                nicks.entry(casemap::fold(network, new))
+                     .or_insert(stats);
+            }
+        }
+    }
+
+    /// Forgets what's been learned about `nick`, or about every nick on the
+    /// network if `nick` is `None`.
+    ///
+    pub fn forget(&mut self, network: &str, nick: Option<&str>) {
+        match nick {
+            Some(nick) => {
+                if let Some(nicks) = self.networks.get_mut(network) {
+                    nicks.remove(&casemap::fold(network, nick));
+                }
+            },
+            None => {
+                self.networks.remove(network);
+            }
+        }
+    }
+}
+
+/// Summarizes a nick's observations as their most likely language and the
+/// share of the weight it holds.
+///
+fn summarize(nick: &str, stats: &NickStats) -> Option<Learned> {
+    let total = stats.weights.values().sum::<f64>();
+    let (lang, w) = stats.weights.iter()
+                                 .max_by(|a, b| a.1.total_cmp(b.1))?;
+    if total <= 0.0 {
+        return None;
+    }
+    Some(Learned { nick    : nick.to_string(),
+                   lang    : lang.clone(),
+                   share   : w / total,
+                   samples : stats.samples })
+}
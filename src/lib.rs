@@ -8,23 +8,50 @@
 //! # The addon provides the following commands
 //! 
 //! * `/LISTLANG` - Lists the names and 2 character codes for all the supported 
-//!                 languages. The names or codes can be used to turn on 
-//!                 translation with `/SETLANG`.
+//!   languages. The names or codes can be used to turn on 
+//!   translation with `/SETLANG`.
 //! * `/SETLANG`  - Sets the source language (of the user) and the target 
-//!                 language to translate to/from for the user.
+//!   language to translate to/from for the user.
 //! * `/LSAY`     - Like `/SAY`, but performs translation. Required for
-//!                 outgoing translations. Without using this command, the 
-//!                 user's messages are sent normally. With the command they're
-//!                 translated and sent to the channel.
+//!   outgoing translations. Without using this command, the 
+//!   user's messages are sent normally. With the command they're
+//!   translated and sent to the channel.
 //! * `/LME`      - A translator version of the `/ME` command.
+//! * `/LTOPIC`   - Sets the channel's topic, translated.
+//! * `/LMSG`     - Like `/MSG`, but translates the message into the language
+//!   of the nick or channel it's sent to. Works from any window.
+//! * `/LNOTICE`  - Like `/LMSG`, but sends a notice.
+//! * `/LPREVIEW` - Shows the translation of a message, and its translation 
+//!   back, without sending it. The translation is placed in the
+//!   input box to be edited or sent.
 //! * `/OFFLANG`  - Turns translation off in the current window.
+//! * `/LUSER`    - Sets the language a nick's messages are translated from,
+//!   overriding the channel's language for that nick.
+//! * `/LSET`     - Shows or changes the translation settings of the current
+//!   channel.
+//! * `/LWHO`     - Lists the languages learned for the nicks on the network.
+//! * `/LDETECT`  - Identifies the language of the given text locally, without
+//!   using the translation service.
+//! * `/LFORMALITY` - Sets the formality of messages to a nick.
+//! * `/LSTATUS`  - Shows the languages and settings in effect for the current
+//!   channel.
+//! * `/LRULE`    - Manages the rewrite rules applied before and after
+//!   translation.
+//! * `/LGLOSSARY` - Manages DeepL glossaries and the local glossaries, and
+//!   which ones the current channel uses.
+//!
+//! With `/LSET autosay on`, plain text typed into a channel is translated as
+//! if it were sent with `/LSAY`. Lines starting with a backslash are then
+//! sent as typed, minus the backslash.
 //!
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
+use std::path::PathBuf;
 use std::format as fm;
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
@@ -32,11 +59,59 @@ use hexchat_api::*;
 use StripFlags::*;
 use UserData::*;
 
+mod casemap;
+mod config;
+mod context;
+mod format;
+mod glossary;
+mod langid;
+mod learn;
+mod protect;
+mod rules;
+mod sanitize;
+mod split;
+
+use config::{Config, DeepLOptions};
+use context::Conversations;
+use learn::NickLangs;
+use rules::{Direction, Rule, Stage};
+
 /// How long to wait for a translation to come back from the translation
 /// server. The unit is seconds.
 ///
 const TRANSLATION_SERVER_TIMEOUT: u64 = 5;
 
+/// How often to check whether a glossary looked up by `/LGLOSSARY USE` has
+/// come back from DeepL. The unit is milliseconds.
+///
+const GLOSSARY_POLL_MS: i64 = 100;
+
+/// The minimum confidence the local language identifier must have that an
+/// incoming message is already in the user's language before the message
+/// is shown untouched, without sending it to the translation service.
+///
+const LOCAL_DETECT_MIN_CONFIDENCE: f64 = 0.8;
+
+/// The prefix that sends a line typed into a channel with `autosay` on as
+/// it is, without translating it. The prefix itself is removed.
+///
+const RAW_PREFIX: &str = "\\";
+
+/// Back-translations less similar than this to what the user wrote are
+/// flagged as possible mistranslations. See `similarity()`.
+///
+const BACK_CHECK_MIN_SIMILARITY: f64 = 0.5;
+
+/// The language code that can be given as the other party's language in
+/// `/SETLANG` to have DeepL detect the language of each incoming message.
+///
+const AUTO_LANG: &str = "auto";
+
+/// The languages DeepL can translate into with a given formality.
+///
+const FORMALITY_LANGS: [&str; 9] = ["de", "es", "fr", "it", "ja", "nl", "pl", 
+                                    "pt", "ru"];
+
 /// DeepL API endpoint for translation
 const DEEPL_API_URL: &str = "https://api-free.deepl.com/v2/translate";
 
@@ -52,6 +127,57 @@ struct DeepLRequest {
     text: Vec<String>,
     source_lang: Option<String>,
     target_lang: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag_handling: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ignore_tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    glossary_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    formality: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    split_sentences: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preserve_formatting: Option<bool>,
+}
+
+/// Options that change how a translation is requested, beyond the text and
+/// languages.
+///
+#[derive(Default, Clone, Debug)]
+struct TranslateOptions {
+    /// The text is XML markup whose tags DeepL should preserve.
+    xml: bool,
+
+    /// The nicks of the users in the channel, which are kept untranslated.
+    nicks: Vec<String>,
+
+    /// The rewrite rules to apply before and after translating.
+    rules: Vec<Rule>,
+
+    /// The IDs of the DeepL glossaries to use, keyed by language pair. The
+    /// one for the languages translated between is used, if any.
+    glossaries: HashMap<String, String>,
+
+    /// The directory holding the local glossaries, which are enforced for
+    /// language pairs without a DeepL glossary.
+    glossary_dir: Option<PathBuf>,
+
+    /// The formality to translate with, used if the target language 
+    /// supports it.
+    formality: Option<String>,
+
+    /// The recent lines of the conversation, which help DeepL translate the
+    /// text but aren't translated themselves.
+    context: Option<String>,
+
+    /// The model type, sentence splitting and formatting options to pass
+    /// on to DeepL.
+    deepl: DeepLOptions,
 }
 
 /// DeepL API response structure
@@ -60,9 +186,15 @@ struct DeepLResponse {
     translations: Vec<DeepLTranslation>,
 }
 
-#[derive(Deserialize)]
+/// A single translation from the DeepL response. When the request omits
+/// `source_lang`, `detected_source_language` holds the language DeepL
+/// detected for the text.
+///
+#[derive(Deserialize, Clone)]
 struct DeepLTranslation {
     text: String,
+    #[serde(default)]
+    detected_source_language: Option<String>,
 }
 
 // Register the entry points of the plugin.
@@ -83,6 +215,68 @@ type ChanData = (String, String);
 ///
 type ChanMap  = HashMap<ChanData, ChanData>;
 
+/// The languages learned for each nick. It's updated from the translation
+/// threads, so it's shared behind a mutex rather than through `UserData`.
+///
+type NickLangsRef = Arc<Mutex<NickLangs>>;
+
+/// A text event whose text is translated, with the positions of its fields
+/// in the `word` its print hook receives.
+///
+struct TextEvent {
+    /// The name of the event.
+    name    : &'static str,
+
+    /// The `/LSET` option that turns its translation on for a channel, or
+    /// `None` if it's always translated.
+    option  : Option<&'static str>,
+
+    /// The field holding the nick of whoever caused the event, if any.
+    sender  : Option<usize>,
+
+    /// The field holding the text to translate.
+    text    : usize,
+
+    /// Whether the event is shown as it is, with its translation printed
+    /// below it, rather than shown translated with the original below.
+    below   : bool,
+}
+
+impl TextEvent {
+    const fn new(name   : &'static str, 
+                 option : Option<&'static str>,
+                 sender : Option<usize>, 
+                 text   : usize) 
+        -> Self 
+    {
+        TextEvent { name, option, sender, text, below: false }
+    }
+
+    const fn below(self) -> Self {
+        TextEvent { below: true, ..self }
+    }
+}
+
+/// The text events that are translated.
+///
+const TEXT_EVENTS: [TextEvent; 15] = [
+    TextEvent::new("Channel Message",           None, Some(0), 1),
+    TextEvent::new("Channel Msg Hilight",       None, Some(0), 1),
+    TextEvent::new("Channel Action",            None, Some(0), 1),
+    TextEvent::new("Channel Action Hilight",    None, Some(0), 1),
+    TextEvent::new("Private Message",           None, Some(0), 1),
+    TextEvent::new("Private Message to Dialog", None, Some(0), 1),
+    TextEvent::new("Private Action",            None, Some(0), 1),
+    TextEvent::new("Private Action to Dialog",  None, Some(0), 1),
+    TextEvent::new("Notice",           Some("notices"), Some(0), 1),
+    TextEvent::new("Channel Notice",   Some("notices"), Some(0), 2),
+    TextEvent::new("Topic",            Some("topics"),  None,    1).below(),
+    TextEvent::new("Topic Change",     Some("topics"),  Some(0), 1).below(),
+    TextEvent::new("Part with Reason", Some("parts"),   Some(0), 3),
+    TextEvent::new("Quit",             Some("quits"),   Some(0), 1),
+    TextEvent::new("Kick",             Some("kicks"),   Some(0), 3),
+];
+
 /// Called when the plugin is loaded to register it with Hexchat.
 ///
 fn plugin_info() -> PluginInfo {
@@ -101,9 +295,33 @@ fn plugin_init(hc: &Hexchat) -> i32 {
     // `map_udata` holds a `HashMap` that maps contexts, `(network, channel)`, 
     // to chosen translation, `(source_lang, target_lang)`. 
     let map_udata  = UserData::shared(HashMap::<ChanData, ChanData>::new());
+
+    // `conf_udata` holds the persistent settings loaded from the config file.
+    let conf_udata = UserData::shared(Config::load(hc));
+
+    // `nick_langs` records the languages nicks have been seen writing in.
+    let nick_langs = NickLangsRef::default();
+
+    // `conv_udata` holds the recent lines of each conversation.
+    let conv_udata = UserData::shared(Conversations::default());
     
-    let lsay_udata = UserData::boxed(("SAY", map_udata.clone()));
-    let lme_udata  = UserData::boxed(("ME", map_udata.clone()));
+    let lsay_udata = UserData::boxed(("SAY", map_udata.clone(), 
+                                             conf_udata.clone(),
+                                             conv_udata.clone()));
+    let lme_udata  = UserData::boxed(("ME",  map_udata.clone(), 
+                                             conf_udata.clone(),
+                                             conv_udata.clone()));
+    let ltopic_udata = UserData::boxed(("TOPIC", map_udata.clone(), 
+                                                 conf_udata.clone(),
+                                                 conv_udata.clone()));
+    let lmsg_udata = UserData::boxed(("MSG", map_udata.clone(), 
+                                             conf_udata.clone(),
+                                             conv_udata.clone(),
+                                             nick_langs.clone()));
+    let lnotice_udata = UserData::boxed(("NOTICE", map_udata.clone(), 
+                                                   conf_udata.clone(),
+                                                   conv_udata.clone(),
+                                                   nick_langs.clone()));
     
     // Register the commands.
     
@@ -111,8 +329,8 @@ fn plugin_init(hc: &Hexchat) -> i32 {
         "LISTLANG", Priority::Norm, on_cmd_listlang, LISTLANG_HELP, NoData);
         
     hc.hook_command(
-        "SETLANG", Priority::Norm, on_cmd_setlang,   SETLANG_HELP, map_udata
-                                                                   .clone());
+        "SETLANG", Priority::Norm, on_cmd_setlang,   SETLANG_HELP, 
+                   UserData::boxed((map_udata.clone(), conf_udata.clone())));
     hc.hook_command(
         "OFFLANG", Priority::Norm, on_cmd_offlang,   OFFLANG_HELP, map_udata
                                                                    .clone());
@@ -122,21 +340,79 @@ fn plugin_init(hc: &Hexchat) -> i32 {
     hc.hook_command(
         "LME",     Priority::Norm, on_cmd_lsay,      LME_HELP,     lme_udata);
 
+    hc.hook_command(
+        "LTOPIC",  Priority::Norm, on_cmd_lsay,      LTOPIC_HELP,  ltopic_udata);
+
+    hc.hook_command(
+        "LMSG",    Priority::Norm, on_cmd_lmsg,      LMSG_HELP,    lmsg_udata);
+
+    hc.hook_command(
+        "LNOTICE", Priority::Norm, on_cmd_lmsg,      LNOTICE_HELP, 
+                   lnotice_udata);
+
+    hc.hook_command(
+        "LUSER",   Priority::Norm, on_cmd_luser,     LUSER_HELP,   conf_udata
+                                                                   .clone());
+    hc.hook_command(
+        "LFORMALITY", Priority::Norm, on_cmd_lformality, LFORMALITY_HELP, 
+                                                         conf_udata.clone());
+    // The nameless command receives text typed without a command.
+    hc.hook_command(
+        "",        Priority::Norm, on_cmd_default,   "",           
+                   UserData::boxed((map_udata.clone(), conf_udata.clone())));
+    hc.hook_command(
+        "LPREVIEW", Priority::Norm, on_cmd_lpreview, LPREVIEW_HELP,
+                   UserData::boxed((map_udata.clone(), conf_udata.clone(),
+                                    conv_udata.clone())));
+    hc.hook_command(
+        "LSET",    Priority::Norm, on_cmd_lset,      LSET_HELP,    conf_udata
+                                                                   .clone());
+    hc.hook_command(
+        "LSTATUS", Priority::Norm, on_cmd_lstatus,   LSTATUS_HELP, 
+                   UserData::boxed((map_udata.clone(), conf_udata.clone())));
+    hc.hook_command(
+        "LWHO",    Priority::Norm, on_cmd_lwho,      LWHO_HELP,    
+                                   UserData::boxed(nick_langs.clone()));
+    hc.hook_command(
+        "LDETECT", Priority::Norm, on_cmd_ldetect,   LDETECT_HELP, NoData);
+
+    hc.hook_command(
+        "LRULE",   Priority::Norm, on_cmd_lrule,     LRULE_HELP,   conf_udata
+                                                                   .clone());
+    hc.hook_command(
+        "LGLOSSARY", Priority::Norm, on_cmd_lglossary, LGLOSSARY_HELP, 
+                                                       conf_udata.clone());
+
 
     // Register the handler for all the interesting text events.
     
-    for event in &["Channel Message", "Channel Msg Hilight", 
-                   "Channel Action",  "Channel Action Hilight", 
-                   "Private Message", "Private Message to Dialog",
-                   "Private Action",  "Private Action to Dialog", 
-                   "You Part",        "You Part with Reason", 
-                   "Disconnected"] 
-    {
-        let event_udata = UserData::boxed((*event, map_udata.clone()));
+    for event in &TEXT_EVENTS {
+        let event_udata = UserData::boxed((event, 
+                                           map_udata.clone(), 
+                                           conf_udata.clone(),
+                                           nick_langs.clone(),
+                                           conv_udata.clone()));
         
-        hc.hook_print(event, Priority::Norm, on_recv_message, event_udata);
+        hc.hook_print(event.name, Priority::Norm, on_recv_message, 
+                      event_udata);
+    }
+
+    // Follow nick changes, both others' and the user's own, so what's set 
+    // for a nick isn't lost when it changes.
+
+    for event in &["Change Nick", "Your Nick Changing"] {
+        let nick_udata = UserData::boxed((map_udata.clone(),
+                                          conf_udata.clone(),
+                                          nick_langs.clone(),
+                                          conv_udata.clone()));
+
+        hc.hook_print(event, Priority::Norm, on_nick_change, nick_udata);
     }
 
+    // Learn each server's case mapping from its ISUPPORT reply.
+
+    hc.hook_server("005", Priority::Norm, on_isupport, NoData);
+
     1
 }
 
@@ -166,6 +442,7 @@ fn get_channel_langs(hc        : &Hexchat,
 {
     let network = hc.get_info("network")?;
     let channel = hc.get_info("channel")?;
+    let channel = casemap::fold(&network, &channel);
     map_udata.apply(
         |chan_map: &ChanMap| {
             chan_map.get(&(network, channel)).cloned()
@@ -188,7 +465,7 @@ fn activate(hc        : &Hexchat,
 {
     let try_activate = || {
         let network = hc.get_info("network")?;
-        let channel = hc.get_info("channel")?;
+        let channel = casemap::fold(&network, &hc.get_info("channel")?);
         map_udata.apply_mut(
             |chan_map: &mut ChanMap| {
                 chan_map.insert((network, channel), 
@@ -212,7 +489,7 @@ fn deactivate(hc        : &Hexchat,
 {
     let try_deactivate = || {
         let network = hc.get_info("network")?;
-        let channel = hc.get_info("channel")?;
+        let channel = casemap::fold(&network, &hc.get_info("channel")?);
         map_udata.apply_mut(
             |chan_map: &mut ChanMap| {
                 chan_map.remove(&(network, channel))
@@ -232,33 +509,69 @@ fn deactivate(hc        : &Hexchat,
 fn on_cmd_setlang(hc        : &Hexchat, 
                   word      : &[String], 
                   _word_eol : &[String], 
-                  map_udata : &UserData) 
+                  user_data : &UserData) 
     -> Eat 
 {
-    if word.len() == 3 {
-        let mut src_lang = word[1].as_str();
-        let mut tgt_lang = word[2].as_str();
+    let (ref map_udata, ref conf_udata) = user_data.apply(
+        |ud: &(UserData, UserData)| (ud.0.clone(), ud.1.clone()));
+
+    if word.len() == 3 || word.len() == 4 {
+        let src_lang = word[1].as_str();
         
         let mut params_good = false;
-        
+
+        // An optional formality for translations into the channel's 
+        // languages.
+        let formality = match word.get(3).map(|f| config::parse_formality(f)) {
+            Some(Ok(formality)) => Some(formality),
+            Some(Err(err)) => {
+                hc.print(&fm!("{IRC_MAGENTA}{}", err));
+                return Eat::All;
+            },
+            None => None,
+        };
+
+        // The other party's language can be "auto", in which case DeepL
+        // detects the language of each incoming message. Or it can be a
+        // comma separated list of languages to translate outgoing messages
+        // to. Empty pieces, as in "de,,fr" or "de,", are rejected.
+        let tgt_lang_infos = word[2].split(',')
+                                    .map(|l| if l.is_empty() { None }
+                                             else { find_lang_or_auto(l) })
+                                    .collect::<Option<Vec<_>>>();
+
         // Verify each lang is in the list below.
-        if let (Some(src_lang_info), Some(tgt_lang_info))
-            = (find_lang(src_lang), find_lang(tgt_lang)) {
+        if let (Some(src_lang_info), Some(tgt_lang_infos))
+            = (find_lang(src_lang), tgt_lang_infos) {
+
+            let is_auto = tgt_lang_infos.iter().any(|i| i.1 == AUTO_LANG);
         
-            if src_lang_info !=  tgt_lang_info {
+            if !tgt_lang_infos.contains(&src_lang_info) 
+                && (!is_auto || tgt_lang_infos.len() == 1) {
                 params_good = true;
                     
                 // Make sure the language names are the abbreviation.
-                src_lang  =  src_lang_info.1;
-                tgt_lang  =  tgt_lang_info.1;
+                let tgt_lang  = tgt_lang_infos.iter()
+                                              .map(|i| i.1)
+                                              .collect::<Vec<_>>()
+                                              .join(",");
+                let tgt_names = tgt_lang_infos.iter()
+                                              .map(|i| i.0)
+                                              .collect::<Vec<_>>()
+                                              .join(", ");
 
                 // Activate the channel.
-                activate(hc, map_udata, src_lang, tgt_lang);
+                activate(hc, map_udata, src_lang_info.1, &tgt_lang);
                 
                 hc.print(&fm!("{IRC_MAGENTA}\
                          TRANSLATION IS ON FOR THIS CHANNEL! \
                          {} (you) to {} (them).", src_lang_info.0, 
-                                                  tgt_lang_info.0));
+                                                  tgt_names));
+
+                if let Some(formality) = formality {
+                    set_channel_formality(hc, conf_udata, formality, 
+                                          &tgt_lang_infos);
+                }
             } 
         }
         if !params_good {
@@ -274,6 +587,35 @@ fn on_cmd_setlang(hc        : &Hexchat,
     Eat::All
 }
 
+/// Sets the formality for the current channel, and warns the user if none
+/// of the channel's languages support it.
+///
+fn set_channel_formality(hc         : &Hexchat,
+                         conf_udata : &UserData,
+                         formality  : Option<String>,
+                         tgt_langs  : &[&(&str, &str)])
+{
+    let (Some(network), Some(channel)) = (hc.get_info("network"),
+                                          hc.get_info("channel")) else {
+        return;
+    };
+    let level = formality.clone().unwrap_or_else(|| "default".into());
+    conf_udata.apply_mut(
+        |conf: &mut Config| {
+            conf.channel_mut(&network, &channel).formality = formality
+        });
+    save_config(hc, conf_udata);
+    hc.print(&fm!("{IRC_MAGENTA}Formality set to {} for {}.", 
+                  level, channel));
+
+    if !tgt_langs.iter().any(|info| supports_formality(info.1)) {
+        hc.print(&fm!("{IRC_MAGENTA}\
+                 None of the channel's languages support formality, so it \
+                 won't have any effect. It's supported for {}.", 
+                 FORMALITY_LANGS.join(", ")));
+    }
+}
+
 /// Implements the /OFFLANG command. Turns translation off in the 
 /// open window/channel.
 ///
@@ -294,7 +636,8 @@ fn on_cmd_offlang(hc        : &Hexchat,
 
 /// Implements the /LSAY and /LME commands. Use /LSAY or /LME followed 
 /// by whatever text you want. The text will be translated and posted to 
-/// the channel. Other users will only see the translated message.
+/// the channel. Other users will only see the translated message. /LTOPIC
+/// is implemented here too, setting the channel's topic to the translation.
 ///
 fn on_cmd_lsay(hc        : &Hexchat, 
                word      : &[String], 
@@ -307,53 +650,149 @@ fn on_cmd_lsay(hc        : &Hexchat,
     } else {
         hc.print(&fm!("{IRC_MAGENTA}\
                  Translator Error: Basic failure retrieving channel \
-                 information, or unable to strip original message."));        
+                 information."));        
         Eat::All
     }
 }
 
 fn try_on_cmd_lsay(hc        : &Hexchat, 
-                   _word     : &[String], 
+                   word      : &[String], 
                    word_eol  : &[String], 
                    user_data : &UserData) 
     -> Option<Eat>
 {
-    // Unpackage the user data to get which command this is for (LSAY/LME),
-    // and get the `UserData` with the `HashMap` in it.
-    let (cmd, ref map_udata) = user_data.apply(
-                                    |ud: &(&str, UserData)| {
-                                        (ud.0, ud.1.clone())
-                                    });
+    // Unpackage the user data to get which command this is for (LSAY/LME/
+    // LTOPIC), and get the `UserData` with the `HashMap` in it, the settings,
+    // and the conversations.
+    let (cmd, ref map_udata, ref conf_udata, ref conv_udata) = user_data.apply(
+                            |ud: &(&str, UserData, UserData, UserData)| {
+                                (ud.0, ud.1.clone(), ud.2.clone(), ud.3.clone())
+                            });
+    let is_topic = cmd == "TOPIC";
+
+    if word.len() < 2 {
+        let help = match cmd {
+            "ME"    => LME_HELP,
+            "TOPIC" => LTOPIC_HELP,
+            _       => LSAY_HELP,
+        };
+        hc.print(&fm!("USAGE: {}", help));
+        return Some(Eat::All);
+    }
 
     if let Some(chan_langs) = get_channel_langs(hc, map_udata) {
         let src_lang  = chan_langs.0;
         let tgt_lang  = chan_langs.1;
         let message   = word_eol[1].clone();
-        
-        let strip_msg = hc.strip(&message, StripBoth)?;
+
+        if tgt_lang == AUTO_LANG {
+            hc.print(&fm!("{IRC_MAGENTA}\
+                     The channel's language is set to auto-detect, so there \
+                     is no language to translate outgoing messages to. Use \
+                     /SETLANG to choose one."));
+            return Some(Eat::All);
+        }
+
         let network   = hc.get_info("network")?;                              
         let channel   = hc.get_info("channel")?;
+        let settings  = conf_udata.apply(
+                            |conf: &Config| conf.channel(&network, &channel));
+        let max_bytes = split::max_message_bytes(&hc.get_info("nick")?, 
+                                                 &channel, 
+                                                 cmd == "ME");
+        let mut opts  = translate_options(hc, conf_udata, conv_udata, 
+                                          &network, &channel, Direction::Out);
+        if let Some(f) = addressee_formality(conf_udata, &network, &channel,
+                                             &message, &opts.nicks) {
+            opts.formality = Some(f);
+        }
+
+        if settings.confirm {
+            // Show the translation for the user to check and send instead.
+            preview_translation(cmd, network, channel, src_lang, tgt_lang, 
+                                message, max_bytes, opts);
+            return Some(Eat::All);
+        }
+        if !is_topic {
+            record_line(hc, conv_udata, &network, &channel, 
+                        &hc.get_info("nick")?, &message);
+        }
 
         thread::spawn(move || {
-            let msg;
             let mut emsg = None;
             let mut is_over_limit = false;
-            
-            match deepl_translate(&strip_msg, &src_lang, &tgt_lang) {
-                Ok(trans) => { 
-                    msg  = trans;
-                },
-                Err(err)  => { 
-                    msg  = err.get_partial_trans().to_string();
-                    emsg = Some(fm!("{IRC_MAGENTA}{}", err));
-                    is_over_limit = err.is_over_limit();
+            let mut notes = vec![];
+
+            // Only the text after an addressing prefix, like "nick: ", is
+            // translated. The prefix is put back on each line sent.
+            let (prefix, body) = protect::split_address(&message, &opts.nicks);
+
+            let mut parts = vec![];
+            for (tgt, trans) in translate_to_all(body, 
+                                                 &src_lang, 
+                                                 &tgt_lang,
+                                                 &opts) {
+                match trans {
+                    Ok(trans) => {
+                        parts.push((tgt, trans.text, true));
+                    },
+                    Err(err)  => { 
+                        parts.push((tgt, err.get_partial_trans().to_string(),
+                                    false));
+                        emsg = Some(fm!("{IRC_MAGENTA}{}", err));
+                        is_over_limit |= err.is_over_limit();
+                    }
                 }
             }
+            // The translations are checked once they've been sent, so the
+            // check doesn't hold them up.
+            let translated = parts.iter()
+                                  .filter(|(_, _, ok)| *ok)
+                                  .map(|(tgt, text, _)| (tgt.clone(), 
+                                                         text.clone()))
+                                  .collect::<Vec<_>>();
+            let parts = parts.into_iter()
+                             .map(|(tgt, text, _)| (tgt, text))
+                             .collect::<Vec<_>>();
+            let msgs = if let [(_, msg)] = parts.as_slice() {
+                vec![msg.clone()]
+            } else {
+                combine_translations(&parts, 
+                                     settings.separate_lines && !is_topic, 
+                                     max_bytes.saturating_sub(prefix.len()))
+            };
+            // Split any lines too long to send in one IRC message.
+            let mut msgs = msgs.iter()
+                               .map(|msg| fm!("{}{}", prefix, msg))
+                               .flat_map(|msg| split::split_message(&msg, 
+                                                                    max_bytes))
+                               .collect::<Vec<_>>();
+
+            // A channel has only the one topic.
+            if is_topic && msgs.len() > 1 {
+                msgs.truncate(1);
+                notes.push(fm!("{IRC_MAGENTA}The translated topic is too \
+                                long, so only its first part was set."));
+            }
+            // /TOPIC takes a leading channel name as the channel to set the
+            // topic of, so the channel is always given.
+            let cmd = if is_topic { 
+                fm!("{} {}", cmd, channel) 
+            } else { 
+                cmd.to_string() 
+            };
+            let (net, chan, text) = (network.clone(), channel.clone(), 
+                                     message.clone());
             if let Err(err) = main_thread(
                 move |hc| -> Result<(), HexchatError> {
                     if let Some(ctx) = hc.find_context(&network, &channel) {
-                        ctx.command(&fm!("{} {}", cmd, msg))?;
+                        for msg in &msgs {
+                            send_text(&ctx, &cmd, msg)?;
+                        }
                         ctx.print(&fm!("{IRC_CYAN}{}", message))?;
+                        for note in &notes {
+                            ctx.print(note)?;
+                        }
                             
                         if let Some(emsg) = &emsg {
                             ctx.print(emsg)?;
@@ -370,6 +809,16 @@ fn try_on_cmd_lsay(hc        : &Hexchat,
             ).get() {
                 hc_print_th!("{IRC_MAGENTA}{}", err);
             }
+            if settings.back_check && !translated.is_empty() {
+                let (_, body) = protect::split_address(&text, &opts.nicks);
+                let checks = translated.iter()
+                                       .flat_map(|(tgt, trans)| {
+                                           back_check(body, trans, tgt, 
+                                                      &src_lang)
+                                       })
+                                       .collect::<Vec<_>>();
+                print_lines(&net, &chan, checks);
+            }
         });
         Some(Eat::All)
     } else {
@@ -377,126 +826,1011 @@ fn try_on_cmd_lsay(hc        : &Hexchat,
     }
 }
 
-/// Callback invoked when channel events like 'Channel Message' occur. 
-/// If translation is on for the channel, this callback will have it 
-/// translated and update the context window with translated message text.
+/// Prints lines in the window for `channel` on `network`, from a thread
+/// other than the main one.
 ///
-fn on_recv_message(hc        : &Hexchat, 
-                   word      : &[String], 
-                   user_data : &UserData) 
+fn print_lines(network: &str, channel: &str, lines: Vec<String>) {
+    let (network, channel) = (network.to_string(), channel.to_string());
+    if let Err(err) = main_thread(
+        move |hc| -> Result<(), HexchatError> {
+            if let Some(ctx) = hc.find_context(&network, &channel) {
+                for line in &lines {
+                    ctx.print(line)?;
+                }
+            }
+            Ok(())
+        }
+    ).get() {
+        hc_print_th!("{IRC_MAGENTA}{}", err);
+    }
+}
+
+/// Implements the /LMSG and /LNOTICE commands. The text is translated into
+/// the language of the channel or nick it's sent to, or the one given with
+/// `-l`, and sent with /MSG or /NOTICE. They work from any window. A 
+/// message to a nick opens a query window for it, where the message and its
+/// original are shown.
+///
+fn on_cmd_lmsg(hc        : &Hexchat, 
+               word      : &[String], 
+               word_eol  : &[String], 
+               user_data : &UserData) 
     -> Eat 
 {
-    if let Some(eat) = try_on_recv_message(hc, word, user_data) {
+    if let Some(eat) = try_on_cmd_lmsg(hc, word, word_eol, user_data) {
         eat
     } else {
-        // If we get here, either `strip()` or `get_info()` returned None.
         hc.print(&fm!("{IRC_MAGENTA}\
                  Translator Error: Basic failure retrieving channel \
-                 information, or unable to strip original message."));
-        Eat::Hexchat
+                 information."));        
+        Eat::All
     }
 }
 
-fn try_on_recv_message(hc        : &Hexchat, 
-                       word      : &[String],
-                       user_data : &UserData)
-    -> Option<Eat> 
+fn try_on_cmd_lmsg(hc        : &Hexchat, 
+                   word      : &[String], 
+                   word_eol  : &[String], 
+                   user_data : &UserData) 
+    -> Option<Eat>
 {
-    if word.len() < 2 || word.last().unwrap() == "~" {
-        // To avoid recursion, this handler appends the "~" to the end of
-        // each `emit_print()` it generates so it can be caught here.
-        return Some(Eat::None);
+    // Unpackage the user data to get which command this is for (MSG/NOTICE),
+    // the `UserData` with the `HashMap` in it, the settings, the 
+    // conversations, and the learned languages of nicks.
+    let (cmd, ref map_udata, ref conf_udata, ref conv_udata, nick_langs) 
+        = user_data.apply(
+            |ud: &(&str, UserData, UserData, UserData, NickLangsRef)| {
+                (ud.0, ud.1.clone(), ud.2.clone(), ud.3.clone(), ud.4.clone())
+            });
+    let help = if cmd == "NOTICE" { LNOTICE_HELP } else { LMSG_HELP };
+
+    let (lang, text_idx) = match word.get(2).map(String::as_str) {
+        Some("-l") => (word.get(3).map(String::as_str), 4),
+        _          => (None, 2),
+    };
+    if word.len() <= text_idx {
+        hc.print(&fm!("USAGE: {}", help));
+        return Some(Eat::All);
+    }
+    let network  = hc.get_info("network")?;
+    let target   = word[1].clone();
+    let message  = word_eol[text_idx].clone();
+    let is_chan  = target.starts_with(['#', '&', '+', '!']);
+    let langs    = map_udata.apply(
+                       |chan_map: &ChanMap| {
+                           chan_map.get(&(network.clone(), 
+                                          casemap::fold(&network, &target)))
+                                   .cloned()
+                       });
+
+    // The language given with -l takes precedence over the target window's
+    // language. For a nick without an active window, the language set with 
+    // /LUSER or learned for it is used.
+    let tgt_lang = match lang {
+        Some(lang) => {
+            let Some(info) = find_lang(lang) else {
+                hc.print(&fm!("{IRC_MAGENTA}\
+                         BAD LANGUAGE PARAMETER. Use /LISTLANG to get a \
+                         list of supported languages."));
+                return Some(Eat::All);
+            };
+            Some(info.1.to_string())
+        },
+        None => langs.as_ref()
+                     .map(|l| l.1.clone())
+                     .or_else(|| if is_chan { None } else {
+                         conf_udata.apply(
+                             |conf: &Config| {
+                                 conf.user_lang(&network, &target)
+                                     .map(str::to_string)
+                             })
+                             .filter(|l| l != AUTO_LANG)
+                             .or_else(|| nick_langs.lock().unwrap()
+                                                   .language(&network, 
+                                                             &target))
+                     })
+                     .filter(|l| l != AUTO_LANG),
+    };
+    let Some(tgt_lang) = tgt_lang else {
+        hc.print(&fm!("{IRC_MAGENTA}\
+                 There's no language known for {}. Give one with -l, or \
+                 use /SETLANG in its window.", target));
+        return Some(Eat::All);
+    };
+    // The user's language is taken from the target's window, or failing
+    // that, the current one. Without either, DeepL detects it.
+    let src_lang = langs.map(|l| l.0)
+                        .or_else(|| get_channel_langs(hc, map_udata)
+                                        .map(|l| l.0))
+                        .unwrap_or_else(|| AUTO_LANG.to_string());
+
+    if !is_chan {
+        hc.command(&fm!("QUERY -nofocus {}", target));
+    }
+    let from      = hc.get_info("channel")?;
+    let settings  = conf_udata.apply(
+                        |conf: &Config| conf.channel(&network, &target));
+    let max_bytes = split::max_message_bytes(&hc.get_info("nick")?, 
+                                             &target, 
+                                             false);
+    let mut opts  = translate_options(hc, conf_udata, conv_udata, 
+                                      &network, &target, Direction::Out);
+    // The nicks to protect are those where the message goes, not those of
+    // the current window.
+    opts.nicks = target_nicks(hc, &network, &target);
+    if let Some(f) = addressee_formality(conf_udata, &network, &target,
+                                         &message, &opts.nicks) {
+        opts.formality = Some(f);
+    }
+    if cmd == "MSG" {
+        record_line(hc, conv_udata, &network, &target, 
+                    &hc.get_info("nick")?, &message);
     }
-    let (event, ref map_udata) = user_data.apply(
-        |ud: &(&str, UserData)| {
-            (ud.0, ud.1.clone())
+
+    thread::spawn(move || {
+        let mut emsg = None;
+
+        let mut parts = vec![];
+        for (tgt, trans) in translate_to_all(&message, 
+                                             &src_lang, 
+                                             &tgt_lang,
+                                             &opts) {
+            match trans {
+                Ok(trans) => parts.push((tgt, trans.text)),
+                Err(err)  => { 
+                    parts.push((tgt, err.get_partial_trans().to_string()));
+                    emsg = Some(fm!("{IRC_MAGENTA}{}", err));
+                }
+            }
+        }
+        let msgs = if let [(_, msg)] = parts.as_slice() {
+            vec![msg.clone()]
+        } else {
+            combine_translations(&parts, settings.separate_lines, max_bytes)
+        };
+        let msgs = msgs.iter()
+                       .flat_map(|msg| split::split_message(msg, max_bytes))
+                       .collect::<Vec<_>>();
+        if let Err(err) = main_thread(
+            move |hc| -> Result<(), HexchatError> {
+                // Messages are sent from the target's window if it's open,
+                // so they're shown there.
+                let ctx = hc.find_context(&network, &target)
+                            .or_else(|| hc.find_context(&network, &from));
+                if let Some(ctx) = ctx {
+                    for msg in &msgs {
+                        send_text(&ctx, &fm!("{} {}", cmd, target), msg)?;
+                    }
+                    ctx.print(&fm!("{IRC_CYAN}{}", message))?;
+
+                    // Unlike /LSAY, translation isn't turned off when over
+                    // the quota, as the window may not be the target's.
+                    if let Some(emsg) = &emsg {
+                        ctx.print(emsg)?;
+                    }
+                } else {
+                    hc.print(&fm!("{IRC_MAGENTA}Failed to get context."));
+                }
+                Ok(())
+            }
+        ).get() {
+            hc_print_th!("{IRC_MAGENTA}{}", err);
+        }
+    });
+    Some(Eat::All)
+}
+
+/// Implements the /LPREVIEW command. Translates the text for the current
+/// channel without sending it. See `preview_translation()`.
+///
+fn on_cmd_lpreview(hc        : &Hexchat,
+                   word      : &[String],
+                   word_eol  : &[String],
+                   user_data : &UserData)
+    -> Eat
+{
+    let (ref map_udata, ref conf_udata, ref conv_udata) = user_data.apply(
+        |ud: &(UserData, UserData, UserData)| {
+            (ud.0.clone(), ud.1.clone(), ud.2.clone())
         });
 
-    if let Some(chan_langs) = get_channel_langs(hc, map_udata) {
-        let sender    = word[0].clone();
-        let message   = word[1].clone();
-        let msg_type  = event;
-        let mode_char = if word.len() > 2 
-                             { word[2].clone() } 
-                        else { "".to_string()  };
-        let src_lang  = chan_langs.0;
-        let tgt_lang  = chan_langs.1;
-        
-        let strip_msg = hc.strip(&message, StripBoth)?; // "throw"
+    if word.len() < 2 {
+        hc.print(&fm!("USAGE: {}", LPREVIEW_HELP));
+        return Eat::All;
+    }
+    let try_preview = || {
+        let (src_lang, tgt_lang) = get_channel_langs(hc, map_udata)?;
+        let message   = word_eol[1].clone();
         let network   = hc.get_info("network")?;
         let channel   = hc.get_info("channel")?;
-        
-        thread::spawn(move || {
-            let msg;
-            let mut emsg = None;
-            let mut is_over_limit = false;
-            
-            match deepl_translate(&strip_msg, &tgt_lang, &src_lang) {
-                Ok(trans) => { 
-                    msg = trans;
+        let max_bytes = split::max_message_bytes(&hc.get_info("nick")?, 
+                                                 &channel, 
+                                                 false);
+        if tgt_lang == AUTO_LANG {
+            return None;
+        }
+        let mut opts = translate_options(hc, conf_udata, conv_udata, 
+                                         &network, &channel, Direction::Out);
+        if let Some(f) = addressee_formality(conf_udata, &network, &channel,
+                                             &message, &opts.nicks) {
+            opts.formality = Some(f);
+        }
+        preview_translation("SAY", network, channel, src_lang, tgt_lang, 
+                            message, max_bytes, opts);
+        Some(())
+    };
+    if try_preview().is_none() {
+        hc.print(&fm!("{IRC_MAGENTA}\
+                 Translation isn't on for this channel, or it has no \
+                 language to translate outgoing messages to. Use /SETLANG \
+                 to choose one."));
+    }
+    Eat::All
+}
+
+/// Translates a message without sending it. The translation into each of
+/// the channel's target languages is printed along with a translation of it
+/// back into the user's language, and the message is placed in the input
+/// box as a `/SAY`, `/ME` or `/TOPIC` command, ready to be edited or sent.
+/// # Arguments
+/// * `cmd`       - The command to send the translation with, "SAY", "ME" or
+///   "TOPIC".
+/// * `network`   - The network of the channel.
+/// * `channel`   - The channel to translate for.
+/// * `src_lang`  - The user's language.
+/// * `tgt_lang`  - The channel's target language(s), comma separated.
+/// * `message`   - The message to translate, which may contain formatting.
+/// * `max_bytes` - The longest message that can be sent in one line.
+/// * `opts`      - The options to translate with.
+///
+#[allow(clippy::too_many_arguments)]
+fn preview_translation(cmd       : &'static str,
+                       network   : String,
+                       channel   : String,
+                       src_lang  : String,
+                       tgt_lang  : String,
+                       message   : String,
+                       max_bytes : usize,
+                       opts      : TranslateOptions)
+{
+    thread::spawn(move || {
+        let mut lines = vec![];
+        let mut parts = vec![];
+        let mut emsg  = None;
+
+        let (prefix, body) = protect::split_address(&message, &opts.nicks);
+
+        for (tgt, trans) in translate_to_all(body, &src_lang, 
+                                             &tgt_lang, &opts) {
+            match trans {
+                Ok(trans) => {
+                    lines.push(fm!("{IRC_CYAN}[{}] {}{}", 
+                                   tgt, prefix, trans.text));
+                    lines.extend(back_check(body, &trans.text, 
+                                            &tgt, &src_lang));
+                    parts.push((tgt, trans.text));
                 },
-                Err(err)  => { 
-                    msg  = err.get_partial_trans().to_string();
+                Err(err) => {
                     emsg = Some(fm!("{IRC_MAGENTA}{}", err));
-                    is_over_limit = err.is_over_limit();
                 }
             }
-            if let Err(err) = main_thread(
-                move |hc| -> Result<(), HexchatError> {
-                    if let Some(ctx) = hc.find_context(&network, &channel) {
-                        if !mode_char.is_empty() {
-                            ctx.emit_print(msg_type, 
-                                           &[&sender, &msg, &mode_char, "~"])?;
-                        } else {
-                            ctx.emit_print(msg_type, 
-                                           &[&sender, &msg, "~"])?;
-                        }
-                        ctx.print(&fm!("{IRC_CYAN}{}", message))?;
-                        if let Some(emsg) = &emsg { 
-                            ctx.print(emsg)?;
-                            if is_over_limit {
-                                ctx.command("OFFLANG")?;
-                            }
+        }
+        let input = if let [(_, msg)] = parts.as_slice() {
+            vec![fm!("{}{}", prefix, msg)]
+        } else {
+            combine_translations(&parts, false, 
+                                 max_bytes.saturating_sub(prefix.len()))
+                .iter()
+                .map(|msg| fm!("{}{}", prefix, msg))
+                .collect()
+        };
+        if let Err(err) = main_thread(
+            move |hc| -> Result<(), HexchatError> {
+                if let Some(ctx) = hc.find_context(&network, &channel) {
+                    ctx.print(&fm!("{IRC_MAGENTA}Translation preview:"))?;
+                    for line in &lines {
+                        ctx.print(line)?;
+                    }
+                    if let Some(emsg) = &emsg {
+                        ctx.print(emsg)?;
+                    }
+                    match input.as_slice() {
+                        [] => {},
+                        [msg] => {
+                            let text = if cmd == "TOPIC" {
+                                fm!("/{} {} {}", cmd, channel, msg)
+                            } else {
+                                fm!("/{} {}", cmd, msg)
+                            };
+                            ctx.command(&fm!("SETTEXT {}", text))?;
+                            ctx.command(&fm!("SETCURSOR {}", 
+                                             text.chars().count()))?;
+                        },
+                        _ => {
+                            ctx.print(&fm!("{IRC_MAGENTA}\
+                                      The translations are too long to \
+                                      place in the input box together."))?;
                         }
-                    } else {
-                        hc.print("Failed to get context.");
                     }
-                    Ok(())
+                } else {
+                    hc.print(&fm!("{IRC_MAGENTA}Failed to get context."));
                 }
-            ).get() {
-                hc_print_th!("{IRC_MAGENTA}{}", err);
+                Ok(())
             }
-        });
-        Some(Eat::Hexchat)
-    } else {
-        Some(Eat::None)
-    }
+        ).get() {
+            hc_print_th!("{IRC_MAGENTA}{}", err);
+        }
+    });
 }
 
-/// Uses the DeepL API service to translate a chat text message to the 
-/// desired target language.
+/// Translates a translation back into the user's language and compares it
+/// with what the user wrote.
 /// # Arguments
-/// * `text`    - The text to translate.
-/// * `source`  - The source language of the text.
-/// * `target`  - The language to translate the text to.
+/// * `original`    - The user's message.
+/// * `translation` - The translation of the message.
+/// * `tgt_lang`    - The language of the translation.
+/// * `src_lang`    - The user's language.
 /// # Returns
-/// * A result where `Ok()` contains the translated text, and `Err()` indicates
-///   the translation failed. The error will contain an aggregate of 
-///   descriptions for each problem encountered during translation.
+/// * The lines to print for the user: the back-translation with its 
+///   similarity to the original, followed by a warning if they differ 
+///   substantially.
 ///
-fn deepl_translate(text   : &str, 
-                   source : &str, 
-                   target : &str)
-
-    -> Result<String, TranslationError> 
+fn back_check(original    : &str,
+              translation : &str,
+              tgt_lang    : &str,
+              src_lang    : &str)
+    -> Vec<String>
 {
-    let api_key = match get_deepl_api_key() {
-        Some(key) => key,
-        None => {
-            return Err(TranslationError::new(
-                text.to_string(),
-                "DeepL API key not found. Set DEEPL_API_KEY environment variable.".to_string(),
-                false
+    let original    = format::strip_codes(original);
+    let translation = format::strip_codes(translation);
+    match deepl_translate(&translation, tgt_lang, src_lang) {
+        Ok(back) => {
+            let sim = similarity(&original, &back.text);
+            let mut lines = vec![fm!("{IRC_GRAY}    back [{}]: {} ({:.0}%)", 
+                                     tgt_lang, back.text, sim * 100.0)];
+            if sim < BACK_CHECK_MIN_SIMILARITY {
+                lines.push(fm!("{IRC_MAGENTA}    The [{}] translation may \
+                                not mean what you wrote.", tgt_lang));
+            }
+            lines
+        },
+        Err(err) => {
+            vec![fm!("{IRC_GRAY}    back [{}]: ({})", tgt_lang, err)]
+        }
+    }
+}
+
+/// Gives a rough measure, from 0.0 to 1.0, of how similar two texts are. It's
+/// the Dice coefficient of the character bigrams of the texts' words, 
+/// ignoring case and punctuation.
+///
+fn similarity(a: &str, b: &str) -> f64 {
+    fn bigrams(text: &str) -> HashMap<(char, char), usize> {
+        let mut grams = HashMap::new();
+        for word in text.to_lowercase()
+                        .split(|c: char| !c.is_alphanumeric())
+                        .filter(|w| !w.is_empty()) {
+            let chars = word.chars().collect::<Vec<_>>();
+            if chars.len() == 1 {
+                *grams.entry((chars[0], ' ')).or_insert(0) += 1;
+            }
+            for pair in chars.windows(2) {
+                *grams.entry((pair[0], pair[1])).or_insert(0) += 1;
+            }
+        }
+        grams
+    }
+    let (ga, gb) = (bigrams(a), bigrams(b));
+    let total    = ga.values().sum::<usize>() + gb.values().sum::<usize>();
+    if total == 0 {
+        return 1.0;
+    }
+    let common   = ga.iter()
+                     .map(|(g, n)| gb.get(g).map_or(0, |m| *n.min(m)))
+                     .sum::<usize>();
+    2.0 * common as f64 / total as f64
+}
+
+/// Translates `text` into each of the comma separated `targets` in 
+/// parallel, with the options given by `opts`.
+/// # Returns
+/// * Each target language paired with the result of translating into it, in
+///   the order the languages were given.
+///
+fn translate_to_all(text    : &str, 
+                    source  : &str, 
+                    targets : &str,
+                    opts    : &TranslateOptions)
+    -> Vec<(String, Result<DeepLTranslation, TranslationError>)>
+{
+    let handles = targets.split(',').map(|tgt| {
+        let text = text.to_string();
+        let src  = source.to_string();
+        let tgt  = tgt.to_string();
+        let opts = opts.clone();
+        thread::spawn(move || {
+            let trans = translate_formatted(&text, &src, &tgt, &opts);
+            (tgt, trans)
+        })
+    }).collect::<Vec<_>>();
+
+    handles.into_iter()
+           .filter_map(|handle| handle.join().ok())
+           .collect()
+}
+
+/// Handles text typed into a window without a command. If the channel is 
+/// active with a language to translate outgoing messages to, and has 
+/// `autosay` on, the text is sent with `/LSAY`, unless it
+/// starts with `RAW_PREFIX`, in which case it's sent as is without the
+/// prefix.
+///
+fn on_cmd_default(hc        : &Hexchat,
+                  _word     : &[String],
+                  word_eol  : &[String],
+                  user_data : &UserData)
+    -> Eat
+{
+    let (ref map_udata, ref conf_udata) = user_data.apply(
+        |ud: &(UserData, UserData)| (ud.0.clone(), ud.1.clone()));
+
+    let (Some(text), Some(network), Some(channel)) = (word_eol.first(),
+                                                      hc.get_info("network"),
+                                                      hc.get_info("channel"))
+    else {
+        return Eat::None;
+    };
+    let auto_say = conf_udata.apply(
+                       |conf: &Config| {
+                           conf.channel(&network, &channel).auto_say
+                       });
+    // Without a language to translate outgoing messages to, the text is
+    // sent as it is.
+    let has_target = get_channel_langs(hc, map_udata)
+                         .is_some_and(|(_, tgt)| tgt != AUTO_LANG);
+    if !auto_say || !has_target {
+        return Eat::None;
+    }
+    if let Some(raw) = text.strip_prefix(RAW_PREFIX) {
+        hc.command(&fm!("SAY {}", raw));
+    } else {
+        hc.command(&fm!("LSAY {}", text));
+    }
+    Eat::All
+}
+
+/// Sends a message to the context's channel with the given command, such as
+/// "SAY", "ME" or "TOPIC #channel". The text is sanitized first so it can't
+/// be interpreted as anything but the text of a single message.
+///
+fn send_text(ctx: &Context, cmd: &str, text: &str) 
+    -> Result<(), HexchatError> 
+{
+    let text = sanitize::sanitize_irc(text);
+    if text.trim().is_empty() {
+        return Ok(());
+    }
+    ctx.command(&fm!("{} {}", cmd, text))
+}
+
+/// Gathers the options for translating messages going in `direction` in the
+/// current channel: the nicks of its users, the rewrite rules, glossaries,
+/// formality and DeepL options that apply to it, and the recent lines of 
+/// the conversation as context.
+///
+fn translate_options(hc         : &Hexchat,
+                     conf_udata : &UserData,
+                     conv_udata : &UserData,
+                     network    : &str,
+                     channel    : &str,
+                     direction  : Direction)
+    -> TranslateOptions
+{
+    conf_udata.apply(
+        |conf: &Config| {
+            let settings = conf.channel(network, channel);
+            let count    = settings.context_lines();
+            let context  = conv_udata.apply(
+                |conv: &Conversations| conv.recent(network, channel, count));
+            TranslateOptions {
+                nicks        : channel_nicks(hc),
+                rules        : conf.rules_for(direction, network, channel),
+                glossaries   : settings.glossaries,
+                glossary_dir : local_glossary_dir(hc),
+                // The formality is for translations into the channel's
+                // languages, not into the user's.
+                formality    : settings.formality
+                                       .filter(|_| direction == Direction::Out),
+                deepl        : settings.deepl.or(&conf.deepl),
+                context,
+                ..Default::default()
+            }
+        })
+}
+
+/// Records a line said in the current channel in its conversation, to be
+/// sent as context with later translations.
+///
+fn record_line(hc         : &Hexchat,
+               conv_udata : &UserData,
+               network    : &str,
+               channel    : &str,
+               nick       : &str,
+               text       : &str)
+{
+    let nick = hc.strip(nick, StripBoth).unwrap_or_else(|| nick.to_string());
+    let text = hc.strip(text, StripBoth).unwrap_or_else(|| text.to_string());
+    conv_udata.apply_mut(
+        |conv: &mut Conversations| conv.record(network, channel, &nick, &text));
+}
+
+/// Gives the formality set with `/LFORMALITY` for the nick an outgoing
+/// message is addressed to, if any. In a query window that's the nick the
+/// window is for; in a channel, it's the nick in the message's addressing
+/// prefix, like "nick: ".
+///
+fn addressee_formality(conf_udata : &UserData,
+                       network    : &str,
+                       channel    : &str,
+                       message    : &str,
+                       nicks      : &[String])
+    -> Option<String>
+{
+    let nick = if channel.starts_with(['#', '&', '+', '!']) {
+        let (prefix, _) = protect::split_address(message, nicks);
+        prefix.trim_end().trim_end_matches([':', ','])
+    } else {
+        channel
+    };
+    conf_udata.apply(
+        |conf: &Config| {
+            conf.user_formality(network, nick).map(str::to_string)
+        })
+}
+
+/// Gives the directory holding the local glossaries.
+///
+fn local_glossary_dir(hc: &Hexchat) -> Option<PathBuf> {
+    let dir = hc.get_info("configdir")?;
+    Some(PathBuf::from(dir).join(glossary::LOCAL_GLOSSARY_DIR))
+}
+
+/// Gives the nicks of the users in the current channel.
+///
+fn channel_nicks(hc: &Hexchat) -> Vec<String> {
+    let Some(users) = hc.list_get("users") else {
+        return vec![];
+    };
+    users.filter_map(|user| user.get_field("nick").ok())
+         .map(FieldValue::str)
+         .collect()
+}
+
+/// Gives the nicks of the users in `target`'s window on `network`, which 
+/// needn't be the current one. If it has none, as with a query or a channel
+/// that isn't open, the target itself is the only nick.
+///
+fn target_nicks(hc: &Hexchat, network: &str, target: &str) -> Vec<String> {
+    let nicks = hc.find_context(network, target)
+                  .and_then(|ctx| ctx.list_get("users").ok())
+                  .map(|users| {
+                      users.filter_map(|user| user.get_field("nick").ok())
+                           .map(FieldValue::str)
+                           .collect::<Vec<_>>()
+                  })
+                  .unwrap_or_default();
+    if nicks.is_empty() {
+        vec![target.to_string()]
+    } else {
+        nicks
+    }
+}
+
+/// Arranges translations of a message into several languages as the lines 
+/// to send. Each translation is tagged with its language. They're combined 
+/// into a single line, unless `separate` is set or the combined line would
+/// be too long to send, in which case each gets its own line.
+/// # Arguments
+/// * `parts`     - The language codes paired with the translation into each.
+/// * `separate`  - Whether to always put the translations on separate lines.
+/// * `max_bytes` - The longest message that can be sent in one line.
+///
+fn combine_translations(parts     : &[(String, String)], 
+                        separate  : bool,
+                        max_bytes : usize) 
+    -> Vec<String> 
+{
+    let tagged = parts.iter()
+                      .map(|(lang, text)| fm!("[{}] {}", lang, text))
+                      .collect::<Vec<_>>();
+    let combined = tagged.join(" | ");
+    if separate || combined.len() > max_bytes {
+        tagged
+    } else {
+        vec![combined]
+    }
+}
+
+/// Callback invoked when the server sends its ISUPPORT (005) reply. The
+/// `CASEMAPPING` token, if present, sets how the network's channel names and
+/// nicks are folded into keys. See the `casemap` module.
+///
+fn on_isupport(hc        : &Hexchat,
+               word      : &[String],
+               _word_eol : &[String],
+               _userdata : &UserData)
+    -> Eat
+{
+    let Some(network) = hc.get_info("network") else {
+        return Eat::None;
+    };
+    // The tokens follow the server, numeric and nick.
+    for token in word.iter().skip(3) {
+        if let Some(value) = token.strip_prefix("CASEMAPPING=") {
+            if let Some(mapping) = casemap::CaseMapping::parse(value) {
+                casemap::set_mapping(&network, mapping);
+            }
+        }
+    }
+    Eat::None
+}
+
+/// Callback invoked when a nick changes. Translation of the nick's query 
+/// window, its /LSET settings, its language and formality overrides, and 
+/// the language learned for it are moved over to the new nick.
+///
+fn on_nick_change(hc        : &Hexchat,
+                  word      : &[String],
+                  user_data : &UserData)
+    -> Eat
+{
+    let (ref map_udata, ref conf_udata, nick_langs, ref conv_udata) 
+        = user_data.apply(
+            |ud: &(UserData, UserData, NickLangsRef, UserData)| {
+                (ud.0.clone(), ud.1.clone(), ud.2.clone(), ud.3.clone())
+            });
+
+    let (Some(old), Some(new), Some(network)) = (word.first(), 
+                                                 word.get(1),
+                                                 hc.get_info("network")) 
+    else {
+        return Eat::None;
+    };
+    let (old, new) = (hc.strip(old, StripBoth).unwrap_or(old.clone()),
+                      hc.strip(new, StripBoth).unwrap_or(new.clone()));
+    if old == new {
+        return Eat::None;
+    }
+    map_udata.apply_mut(
+        |chan_map: &mut ChanMap| {
+            let key = (network.clone(), casemap::fold(&network, &old));
+            if let Some(langs) = chan_map.remove(&key) {
+                chan_map.entry((network.clone(), 
+                                casemap::fold(&network, &new)))
+                        .or_insert(langs);
+            }
+        });
+    let moved = conf_udata.apply_mut(
+                    |conf: &mut Config| conf.rename_nick(&network, &old, &new));
+    if moved {
+        save_config(hc, conf_udata);
+    }
+    nick_langs.lock().unwrap().rename(&network, &old, &new);
+    conv_udata.apply_mut(
+        |conv: &mut Conversations| conv.rename(&network, &old, &new));
+    Eat::None
+}
+
+/// Callback invoked when channel events like 'Channel Message' occur. 
+/// If translation is on for the channel, this callback will have it 
+/// translated and update the context window with translated message text.
+///
+fn on_recv_message(hc        : &Hexchat, 
+                   word      : &[String], 
+                   user_data : &UserData) 
+    -> Eat 
+{
+    if let Some(eat) = try_on_recv_message(hc, word, user_data) {
+        eat
+    } else {
+        // If we get here, either `strip()` or `get_info()` returned None.
+        hc.print(&fm!("{IRC_MAGENTA}\
+                 Translator Error: Basic failure retrieving channel \
+                 information, or unable to strip original message."));
+        Eat::Hexchat
+    }
+}
+
+fn try_on_recv_message(hc        : &Hexchat, 
+                       word      : &[String],
+                       user_data : &UserData)
+    -> Option<Eat> 
+{
+    let (event, ref map_udata, ref conf_udata, nick_langs, ref conv_udata) 
+        = user_data.apply(
+            |ud: &(&TextEvent, UserData, UserData, NickLangsRef, UserData)| {
+                (ud.0, ud.1.clone(), ud.2.clone(), ud.3.clone(), ud.4.clone())
+            });
+
+    // Hexchat stops the fields at the first empty one, so an event without
+    // its text, like a quit without a reason, has nothing to translate.
+    if word.len() <= event.text || word.last().unwrap() == "~" {
+        // To avoid recursion, this handler appends the "~" to the end of
+        // each `emit_print()` it generates so it can be caught here.
+        return Some(Eat::None);
+    }
+
+    if let Some(chan_langs) = get_channel_langs(hc, map_udata) {
+        let network   = hc.get_info("network")?;
+        let channel   = hc.get_info("channel")?;
+
+        if let Some(option) = event.option {
+            let enabled = conf_udata.apply(
+                |conf: &Config| {
+                    conf.channel(&network, &channel).translates(option)
+                });
+            if !enabled {
+                return Some(Eat::None);
+            }
+        }
+        let mut fields = word.to_vec();
+        let sender    = event.sender.map_or("", |i| word[i].as_str());
+        let message   = word[event.text].clone();
+        let msg_type  = event.name;
+        let src_lang  = chan_langs.0;
+        let opts      = translate_options(hc, conf_udata, conv_udata,
+                                          &network, &channel, Direction::In);
+        if !sender.is_empty() {
+            record_line(hc, conv_udata, &network, &channel, sender, &message);
+        }
+
+        // Only the text after an addressing prefix, like "nick: ", is 
+        // translated. The prefix is put back unchanged, so the line still
+        // reads as addressed to the same nick.
+        let (prefix, body) = protect::split_address(&message, &opts.nicks);
+        let (prefix, body) = (prefix.to_string(), body.to_string());
+        
+        let strip_msg = hc.strip(&body, StripBoth)?; // "throw"
+
+        let nick      = hc.strip(sender, StripBoth)?;
+
+        // Identify the message's language locally. A confident result is
+        // recorded toward the sender's learned language, and if it's the 
+        // user's own language, the message is let through untouched 
+        // without spending any translation quota on it.
+        let detected = langid::detect(&strip_msg)
+                           .filter(|det| {
+                               det.confidence >= LOCAL_DETECT_MIN_CONFIDENCE
+                           });
+        if let Some(det) = &detected {
+            if !nick.is_empty() {
+                nick_langs.lock().unwrap()
+                          .observe(&network, &nick, det.lang, det.confidence);
+            }
+            if det.lang == src_lang {
+                return Some(Eat::None);
+            }
+        }
+
+        // In a channel whose language is detected, or that has several,
+        // translations are tagged with the language they were made from.
+        let is_tagged = chan_langs.1 == AUTO_LANG || chan_langs.1.contains(',');
+
+        // A language set for the sender with /LUSER takes precedence over 
+        // the language the message was confidently identified as, which
+        // takes precedence over the language learned for the sender, and
+        // then the channel's language.
+        let tgt_lang  = conf_udata.apply(
+                            |conf: &Config| {
+                                conf.user_lang(&network, &nick)
+                                    .map(str::to_string)
+                            })
+                            .or_else(|| detected.map(|d| d.lang.to_string()))
+                            .or_else(|| nick_langs.lock().unwrap()
+                                                  .language(&network, &nick))
+                            .unwrap_or_else(|| {
+                                // With several target languages, there's no
+                                // single one to translate incoming messages 
+                                // from, so it's detected instead.
+                                if chan_langs.1.contains(',') {
+                                    AUTO_LANG.to_string()
+                                } else {
+                                    chan_langs.1
+                                }
+                            });
+
+        if tgt_lang == src_lang {
+            return Some(Eat::None);
+        }
+        
+        thread::spawn(move || {
+            let msg;
+            let mut emsg = None;
+            let mut is_over_limit = false;
+            let mut is_same_lang  = false;
+            
+            match translate_formatted(&body, &tgt_lang, &src_lang, &opts) {
+                Ok(trans) => {
+                    // DeepL only detects the language when none is given.
+                    match &trans.detected_source_language {
+                        Some(lang) if tgt_lang == AUTO_LANG 
+                                   && !nick.is_empty() => {
+                            let lang = lang.split('-').next().unwrap_or(lang);
+                            nick_langs.lock().unwrap()
+                                      .observe(&network, &nick, lang, 1.0);
+                        },
+                        _ => {}
+                    }
+                    // The language the message was translated from: the one
+                    // given, or failing that, the one DeepL detected.
+                    let used = if tgt_lang == AUTO_LANG {
+                        trans.detected_source_language.clone()
+                    } else {
+                        Some(tgt_lang.clone())
+                    };
+                    // In auto-detect mode, tag the line with that language
+                    // so the user knows what was spoken. If it's already in
+                    // the user's language, the translation is discarded and
+                    // the message is shown untouched.
+                    msg = match &used {
+                        Some(lang) if is_lang(lang, &src_lang) => {
+                            is_same_lang = true;
+                            message.clone()
+                        },
+                        Some(lang) if is_tagged => {
+                            fm!("{IRC_GRAY}[{}]{IRC_ORIG_ATTRIBS} {}{}",
+                                lang.to_lowercase(), prefix, trans.text)
+                        },
+                        _ => fm!("{}{}", prefix, trans.text),
+                    };
+                },
+                Err(err)  => { 
+                    msg  = fm!("{}{}", prefix, err.get_partial_trans());
+                    emsg = Some(fm!("{IRC_MAGENTA}{}", err));
+                    is_over_limit = err.is_over_limit();
+                }
+            }
+            if let Err(err) = main_thread(
+                move |hc| -> Result<(), HexchatError> {
+                    if let Some(ctx) = hc.find_context(&network, &channel) {
+                        if event.below {
+                            // The event was shown untouched.
+                            if !is_same_lang {
+                                ctx.print(&fm!("{IRC_CYAN}{}", msg))?;
+                            }
+                        } else {
+                            fields[event.text] = msg.clone();
+                            fields.push("~".to_string());
+                            let fields = fields.iter()
+                                               .map(String::as_str)
+                                               .collect::<Vec<_>>();
+                            ctx.emit_print(msg_type, &fields)?;
+
+                            if !is_same_lang {
+                                ctx.print(&fm!("{IRC_CYAN}{}", message))?;
+                            }
+                        }
+                        if let Some(emsg) = &emsg { 
+                            ctx.print(emsg)?;
+                            if is_over_limit {
+                                ctx.command("OFFLANG")?;
+                            }
+                        }
+                    } else {
+                        hc.print("Failed to get context.");
+                    }
+                    Ok(())
+                }
+            ).get() {
+                hc_print_th!("{IRC_MAGENTA}{}", err);
+            }
+        });
+        if event.below {
+            Some(Eat::None)
+        } else {
+            Some(Eat::Hexchat)
+        }
+    } else {
+        Some(Eat::None)
+    }
+}
+
+/// Translates text that may contain mIRC formatting codes, keeping the
+/// formatting, and leaves nicks, URLs, channel names, code and emoji 
+/// untranslated. Such text is sent to DeepL as XML markup, and the markup in
+/// the translation is converted back to formatting codes. Other text is 
+/// translated as is. The rewrite rules in `opts` are applied to the text 
+/// before it's translated and to the translation, and if there's no DeepL
+/// glossary for the languages, the local glossary is enforced.
+/// # Arguments
+/// * `text`    - The text to translate.
+/// * `source`  - The source language of the text.
+/// * `target`  - The language to translate the text to.
+/// * `opts`    - Further options for the translation.
+///
+fn translate_formatted(text   : &str,
+                       source : &str,
+                       target : &str,
+                       opts   : &TranslateOptions)
+
+    -> Result<DeepLTranslation, TranslationError>
+{
+    let text = rules::apply(&opts.rules, Stage::Pre, text);
+    let pair = glossary::pair_key(source, target);
+
+    // Terms in the local glossary are replaced with their translations,
+    // which are then kept as they are.
+    let (text, mut keep) = match &opts.glossary_dir {
+        Some(dir) if source != AUTO_LANG 
+                  && !opts.glossaries.contains_key(&pair) => {
+            let entries = glossary::local_entries(dir, source, target);
+            glossary::enforce(&text, &entries)
+        },
+        _ => (text, vec![]),
+    };
+    keep.extend(protect::spans(&text, &opts.nicks));
+    let keep = protect::merge(keep);
+    let text = &text;
+
+    let trans = if keep.is_empty() && !format::has_formatting(text) {
+        deepl_translate_with(text, source, target, opts)?
+    } else {
+        let mut opts = opts.clone();
+        opts.xml = true;
+        let xml = format::to_xml(text, &keep);
+        match deepl_translate_with(&xml, source, target, &opts) {
+            Ok(trans) => {
+                let text = format::from_xml(&trans.text);
+                DeepLTranslation { text: sanitize::sanitize_irc(&text), 
+                                   ..trans }
+            },
+            Err(err) => {
+                return Err(TranslationError::new(text.to_string(), 
+                                                 err.error_msg, 
+                                                 err.over_limit));
+            }
+        }
+    };
+    let text = rules::apply(&opts.rules, Stage::Post, &trans.text);
+    Ok(DeepLTranslation { text, ..trans })
+}
+
+/// Uses the DeepL API service to translate a chat text message to the 
+/// desired target language.
+/// # Arguments
+/// * `text`    - The text to translate.
+/// * `source`  - The source language of the text.
+/// * `target`  - The language to translate the text to.
+/// # Returns
+/// * A result where `Ok()` contains the translation - its text, and the
+///   language DeepL detected if `source` is "auto" - and `Err()` indicates
+///   the translation failed. The error will contain an aggregate of 
+///   descriptions for each problem encountered during translation.
+///
+fn deepl_translate(text   : &str, 
+                   source : &str, 
+                   target : &str)
+
+    -> Result<DeepLTranslation, TranslationError> 
+{
+    deepl_translate_with(text, source, target, &TranslateOptions::default())
+}
+
+/// Like `deepl_translate()`, but with options set on the request.
+///
+fn deepl_translate_with(text   : &str,
+                        source : &str,
+                        target : &str,
+                        opts   : &TranslateOptions)
+
+    -> Result<DeepLTranslation, TranslationError>
+{
+    let api_key = match get_deepl_api_key() {
+        Some(key) => key,
+        None => {
+            return Err(TranslationError::new(
+                text.to_string(),
+                "DeepL API key not found. Set DEEPL_API_KEY environment variable.".to_string(),
+                false
             ));
         }
     };
@@ -514,6 +1848,18 @@ fn deepl_translate(text   : &str,
         text: vec![text.to_string()],
         source_lang: if deepl_source == "auto" { None } else { Some(deepl_source.to_string()) },
         target_lang: deepl_target.to_string(),
+        tag_handling: opts.xml.then(|| "xml".to_string()),
+        ignore_tags: opts.xml.then(|| vec![format::KEEP_TAG.to_string()]),
+        // DeepL only uses a glossary when the source language is given.
+        glossary_id: if deepl_source == "auto" { None } else {
+            opts.glossaries.get(&glossary::pair_key(source, target)).cloned()
+        },
+        formality: opts.formality.clone()
+                                 .filter(|_| supports_formality(target)),
+        context: opts.context.clone(),
+        model_type: opts.deepl.model_type.clone(),
+        split_sentences: opts.deepl.split_sentences.clone(),
+        preserve_formatting: opts.deepl.preserve_formatting,
     };
 
     match agent
@@ -526,7 +1872,14 @@ fn deepl_translate(text   : &str,
             match response.into_json::<DeepLResponse>() {
                 Ok(deepl_response) => {
                     if let Some(translation) = deepl_response.translations.first() {
-                        Ok(translation.text.clone())
+                        // Nothing from the service is used unsanitized.
+                        Ok(DeepLTranslation {
+                            text: sanitize::sanitize(&translation.text),
+                            detected_source_language: 
+                                translation.detected_source_language
+                                           .as_deref()
+                                           .map(sanitize::sanitize_lang_code),
+                        })
                     } else {
                         Err(TranslationError::new(
                             text.to_string(),
@@ -559,6 +1912,25 @@ fn deepl_translate(text   : &str,
     }
 }
 
+/// Indicates whether a language code returned by DeepL, such as the
+/// `detected_source_language` of a translation, refers to the given
+/// language.
+/// # Arguments
+/// * `deepl_lang`  - The DeepL language code, e.g. "EN" or "PT-BR".
+/// * `lang`        - The language code used by the plugin, e.g. "en".
+///
+fn is_lang(deepl_lang: &str, lang: &str) -> bool {
+    let base = deepl_lang.split('-').next().unwrap_or(deepl_lang);
+    base.eq_ignore_ascii_case(map_to_deepl_lang(lang))
+}
+
+/// Indicates whether DeepL can translate into `lang` with a given 
+/// formality.
+///
+fn supports_formality(lang: &str) -> bool {
+    FORMALITY_LANGS.contains(&lang.to_lowercase().as_str())
+}
+
 /// Maps language codes to DeepL-compatible format
 fn map_to_deepl_lang(lang: &str) -> &str {
     match lang.to_lowercase().as_str() {
@@ -630,18 +2002,682 @@ fn on_cmd_listlang(hc        : &Hexchat,
     Eat::All
 }
 
+/// Implements the /LUSER command. Sets the language messages from a nick on
+/// the current network are translated from, which overrides the channel's
+/// language for that nick. Without parameters, the nicks with a language set
+/// on the network are listed.
+///
+fn on_cmd_luser(hc         : &Hexchat,
+                word       : &[String],
+                _word_eol  : &[String],
+                conf_udata : &UserData)
+    -> Eat
+{
+    let Some(network) = hc.get_info("network") else {
+        hc.print(&fm!("{IRC_MAGENTA}\
+                 Failed to get network information."));
+        return Eat::All;
+    };
+    match word.len() {
+        1 => {
+            let mut users = conf_udata.apply(
+                |conf: &Config| {
+                    conf.user_langs.get(&network)
+                                   .map(|u| u.clone().into_iter()
+                                                     .collect::<Vec<_>>())
+                                   .unwrap_or_default()
+                });
+            users.sort();
+            hc.print(&fm!("{IRC_CYAN}User languages on {}:", network));
+            if users.is_empty() {
+                hc.print(&fm!("{IRC_CYAN}  (none)"));
+            }
+            for (nick, lang) in users {
+                hc.print(&fm!("{IRC_CYAN}  {:-20}{}", nick, lang));
+            }
+        },
+        3 => {
+            let nick = &word[1];
+            let lang = word[2].as_str();
+            if lang.eq_ignore_ascii_case("off") {
+                let removed = conf_udata.apply_mut(
+                    |conf: &mut Config| {
+                        conf.remove_user_lang(&network, nick)
+                    });
+                if removed {
+                    save_config(hc, conf_udata);
+                    hc.print(&fm!("{IRC_MAGENTA}\
+                             Language override removed for {}.", nick));
+                } else {
+                    hc.print(&fm!("{IRC_MAGENTA}\
+                             No language override is set for {}.", nick));
+                }
+            } else if let Some(info) = find_lang_or_auto(lang) {
+                conf_udata.apply_mut(
+                    |conf: &mut Config| {
+                        conf.set_user_lang(&network, nick, info.1)
+                    });
+                save_config(hc, conf_udata);
+                hc.print(&fm!("{IRC_MAGENTA}\
+                         Messages from {} will be translated from {}.", 
+                         nick, info.0));
+            } else {
+                hc.print(&fm!("{IRC_MAGENTA}\
+                         BAD LANGUAGE PARAMETER. Use /LISTLANG to get a \
+                         list of supported languages."));
+            }
+        },
+        _ => {
+            hc.print(&fm!("USAGE: {}", LUSER_HELP));
+        }
+    }
+    Eat::All
+}
+
+/// Implements the /LFORMALITY command. Sets the formality of messages sent
+/// to a nick, in a query window or addressed to them with a "nick: " 
+/// prefix. Without parameters, lists the nicks with a formality set.
+///
+fn on_cmd_lformality(hc         : &Hexchat,
+                     word       : &[String],
+                     _word_eol  : &[String],
+                     conf_udata : &UserData)
+    -> Eat
+{
+    let Some(network) = hc.get_info("network") else {
+        hc.print(&fm!("{IRC_MAGENTA}\
+                 Failed to get network information."));
+        return Eat::All;
+    };
+    match word.len() {
+        1 => {
+            let mut users = conf_udata.apply(
+                |conf: &Config| {
+                    conf.user_formality.get(&network)
+                                       .map(|u| u.clone().into_iter()
+                                                         .collect::<Vec<_>>())
+                                       .unwrap_or_default()
+                });
+            users.sort();
+            hc.print(&fm!("{IRC_CYAN}User formality on {}:", network));
+            if users.is_empty() {
+                hc.print(&fm!("{IRC_CYAN}  (none)"));
+            }
+            for (nick, formality) in users {
+                hc.print(&fm!("{IRC_CYAN}  {:-20}{}", nick, formality));
+            }
+        },
+        3 => {
+            let nick  = &word[1];
+            let value = if word[2].eq_ignore_ascii_case("off") {
+                Ok(None)
+            } else {
+                config::parse_formality(&word[2]).map(|f| {
+                    f.or_else(|| Some("default".into()))
+                })
+            };
+            match value {
+                Ok(formality) => {
+                    let msg = match &formality {
+                        Some(f) => fm!("Messages to {} will use formality \
+                                        {}.", nick, f),
+                        None    => fm!("Formality override removed for {}.", 
+                                       nick),
+                    };
+                    conf_udata.apply_mut(
+                        |conf: &mut Config| {
+                            conf.set_user_formality(&network, nick, formality)
+                        });
+                    save_config(hc, conf_udata);
+                    hc.print(&fm!("{IRC_MAGENTA}{}", msg));
+                },
+                Err(err) => {
+                    hc.print(&fm!("{IRC_MAGENTA}{}", err));
+                }
+            }
+        },
+        _ => {
+            hc.print(&fm!("USAGE: {}", LFORMALITY_HELP));
+        }
+    }
+    Eat::All
+}
+
+/// Implements the /LSET command. Without parameters, lists the current 
+/// channel's settings. With an option name and value, changes the setting
+/// for the channel. With `-global`, lists or changes the DeepL options used
+/// in channels that don't set their own.
+///
+fn on_cmd_lset(hc         : &Hexchat,
+               word       : &[String],
+               _word_eol  : &[String],
+               conf_udata : &UserData)
+    -> Eat
+{
+    let (Some(network), Some(channel)) = (hc.get_info("network"),
+                                          hc.get_info("channel")) else {
+        hc.print(&fm!("{IRC_MAGENTA}\
+                 Failed to get channel information."));
+        return Eat::All;
+    };
+    let global = word.get(1)
+                     .is_some_and(|w| w.eq_ignore_ascii_case("-global"));
+    match (global, word.len()) {
+        (true, 2) => {
+            let options = conf_udata.apply(
+                |conf: &Config| conf.deepl.options());
+            hc.print(&fm!("{IRC_CYAN}Global DeepL options:"));
+            for (name, value) in options {
+                hc.print(&fm!("{IRC_CYAN}  {:-20}{}", name, value));
+            }
+        },
+        (true, 4) => {
+            let result = conf_udata.apply_mut(
+                |conf: &mut Config| conf.deepl.set(&word[2], &word[3]));
+            match result {
+                Ok(()) => {
+                    save_config(hc, conf_udata);
+                    hc.print(&fm!("{IRC_MAGENTA}{} set to {} globally.", 
+                                  word[2].to_lowercase(), word[3]));
+                },
+                Err(err) => {
+                    hc.print(&fm!("{IRC_MAGENTA}{}", err));
+                }
+            }
+        },
+        (false, 1) => {
+            let settings = conf_udata.apply(
+                |conf: &Config| conf.channel(&network, &channel));
+            hc.print(&fm!("{IRC_CYAN}Translation settings for {}:", channel));
+            for (name, value) in settings.options() {
+                hc.print(&fm!("{IRC_CYAN}  {:-20}{}", name, value));
+            }
+        },
+        (false, 3) => {
+            let result = conf_udata.apply_mut(
+                |conf: &mut Config| {
+                    conf.channel_mut(&network, &channel)
+                        .set(&word[1], &word[2])
+                });
+            match result {
+                Ok(()) => {
+                    save_config(hc, conf_udata);
+                    hc.print(&fm!("{IRC_MAGENTA}{} set to {} for {}.", 
+                                  word[1].to_lowercase(), word[2], channel));
+                },
+                Err(err) => {
+                    hc.print(&fm!("{IRC_MAGENTA}{}", err));
+                }
+            }
+        },
+        _ => {
+            hc.print(&fm!("USAGE: {}", LSET_HELP));
+        }
+    }
+    Eat::All
+}
+
+/// Implements the /LSTATUS command. Shows the languages and the settings in
+/// effect for the current channel, with the global DeepL options filled in
+/// for those the channel doesn't set.
+///
+fn on_cmd_lstatus(hc        : &Hexchat,
+                  _word     : &[String],
+                  _word_eol : &[String],
+                  user_data : &UserData)
+    -> Eat
+{
+    let (ref map_udata, ref conf_udata) = user_data.apply(
+        |ud: &(UserData, UserData)| (ud.0.clone(), ud.1.clone()));
+
+    let (Some(network), Some(channel)) = (hc.get_info("network"),
+                                          hc.get_info("channel")) else {
+        hc.print(&fm!("{IRC_MAGENTA}\
+                 Failed to get channel information."));
+        return Eat::All;
+    };
+    hc.print(&fm!("{IRC_CYAN}Translation status for {} on {}:", 
+                  channel, network));
+    match get_channel_langs(hc, map_udata) {
+        Some((src_lang, tgt_lang)) => {
+            hc.print(&fm!("{IRC_CYAN}  {:-20}{} to {}", 
+                          "languages", src_lang, tgt_lang));
+        },
+        None => {
+            hc.print(&fm!("{IRC_CYAN}  {:-20}off", "languages"));
+        }
+    }
+    let (mut settings, deepl, rules) = conf_udata.apply(
+        |conf: &Config| {
+            let settings = conf.channel(&network, &channel);
+            let deepl    = settings.deepl.or(&conf.deepl);
+            let applies  = |r: &&Rule| {
+                r.applies(Direction::In,  &network, &channel)
+                    || r.applies(Direction::Out, &network, &channel)
+            };
+            let rules    = conf.rules.iter().filter(applies).count();
+            (settings, deepl, rules)
+        });
+    settings.deepl = deepl;
+    for (name, value) in settings.options() {
+        hc.print(&fm!("{IRC_CYAN}  {:-20}{}", name, value));
+    }
+    let mut glossaries = settings.glossaries.into_iter().collect::<Vec<_>>();
+    glossaries.sort();
+    for (pair, id) in glossaries {
+        hc.print(&fm!("{IRC_CYAN}  {:-20}{} {}", "glossary", pair, id));
+    }
+    hc.print(&fm!("{IRC_CYAN}  {:-20}{}", "rules", rules));
+    Eat::All
+}
+
+/// Writes the settings in `conf_udata` to the config file, printing an error
+/// if that fails.
+///
+fn save_config(hc: &Hexchat, conf_udata: &UserData) {
+    if let Err(err) = conf_udata.apply(|conf: &Config| conf.save(hc)) {
+        hc.print(&fm!("{IRC_MAGENTA}\
+                 Failed to save translator settings: {}", err));
+    }
+}
+
+/// Implements the /LWHO command. Lists the languages learned for nicks on
+/// the current network, or for a single nick. `/LWHO FORGET [<nick>]`
+/// discards what's been learned.
+///
+fn on_cmd_lwho(hc        : &Hexchat,
+               word      : &[String],
+               _word_eol : &[String],
+               user_data : &UserData)
+    -> Eat
+{
+    let nick_langs = user_data.apply(|nl: &NickLangsRef| nl.clone());
+    let Some(network) = hc.get_info("network") else {
+        hc.print(&fm!("{IRC_MAGENTA}\
+                 Failed to get network information."));
+        return Eat::All;
+    };
+    let nick_langs = &mut *nick_langs.lock().unwrap();
+
+    if word.len() > 1 && word[1].eq_ignore_ascii_case("forget") {
+        if word.len() > 3 {
+            hc.print(&fm!("USAGE: {}", LWHO_HELP));
+            return Eat::All;
+        }
+        let nick = word.get(2).map(String::as_str);
+        nick_langs.forget(&network, nick);
+        hc.print(&fm!("{IRC_MAGENTA}Forgot the learned language of {}.",
+                      nick.unwrap_or("all nicks on this network")));
+        return Eat::All;
+    }
+    let learned = match word.len() {
+        1 => nick_langs.all_learned(&network),
+        2 => nick_langs.learned(&network, &word[1]).into_iter().collect(),
+        _ => {
+            hc.print(&fm!("USAGE: {}", LWHO_HELP));
+            return Eat::All;
+        }
+    };
+    hc.print(&fm!("{IRC_CYAN}Learned languages on {}:", network));
+    if learned.is_empty() {
+        hc.print(&fm!("{IRC_CYAN}  (none)"));
+    }
+    for l in learned {
+        hc.print(&fm!("{IRC_CYAN}  {:-20}{:6}{:4.0}% of {:3} messages{}",
+                      l.nick, l.lang, l.share * 100.0, l.samples,
+                      if l.is_confident() { "" } else { "  (learning)" }));
+    }
+    Eat::All
+}
+
+/// Implements the /LDETECT command. Identifies the language of the given
+/// text using the local language identifier and prints the most likely
+/// candidates with their confidence scores.
+///
+fn on_cmd_ldetect(hc        : &Hexchat,
+                  word      : &[String],
+                  word_eol  : &[String],
+                  _userdata : &UserData)
+    -> Eat
+{
+    if word.len() > 1 {
+        let text  = hc.strip(&word_eol[1], StripBoth)
+                      .unwrap_or_else(|| word_eol[1].clone());
+        let cands = langid::detect_all(&text);
+        if cands.is_empty() {
+            hc.print(&fm!("{IRC_MAGENTA}Unable to identify the language \
+                          of the text."));
+        }
+        for det in cands.iter().take(3) {
+            let name = find_lang(det.lang).map_or(det.lang, |info| info.0);
+            hc.print(&fm!("{IRC_CYAN}{:-15}{:3}    confidence: {:.2}",
+                          name, det.lang, det.confidence));
+        }
+    } else {
+        hc.print(&fm!("USAGE: {}", LDETECT_HELP));
+    }
+    Eat::All
+}
+
+/// Implements the /LRULE command, which manages the rewrite rules applied
+/// before and after translation. See `rules.rs`.
+///
+fn on_cmd_lrule(hc         : &Hexchat,
+                word       : &[String],
+                word_eol   : &[String],
+                conf_udata : &UserData)
+    -> Eat
+{
+    let sub = word.get(1).map(|w| w.to_lowercase()).unwrap_or_default();
+    match (sub.as_str(), word.len()) {
+        ("add", 3..) => {
+            let mut direction = Direction::Both;
+            let mut stage     = Stage::Pre;
+            let mut place     = None;
+            let mut i         = 2;
+            while i < word.len() - 1 {
+                match word[i].to_lowercase().as_str() {
+                    "-in"   => direction = Direction::In,
+                    "-out"  => direction = Direction::Out,
+                    "-pre"  => stage     = Stage::Pre,
+                    "-post" => stage     = Stage::Post,
+                    "-here" => place     = hc.get_info("network")
+                                                 .zip(hc.get_info("channel")),
+                    _ => break,
+                }
+                i += 1;
+            }
+            let pattern     = &word[i];
+            let replacement = word_eol.get(i + 1).map_or("", String::as_str);
+            match Rule::new(pattern, replacement, direction, stage, place) {
+                Ok(rule) => {
+                    hc.print(&fm!("{IRC_MAGENTA}Rule added: {}", rule));
+                    conf_udata.apply_mut(
+                        |conf: &mut Config| conf.rules.push(rule));
+                    save_config(hc, conf_udata);
+                },
+                Err(err) => {
+                    hc.print(&fm!("{IRC_MAGENTA}Invalid pattern: {}", err));
+                }
+            }
+        },
+        ("del", 3) => {
+            let removed = word[2].parse::<usize>().ok().and_then(|n| {
+                conf_udata.apply_mut(
+                    |conf: &mut Config| {
+                        (1..=conf.rules.len()).contains(&n)
+                                              .then(|| conf.rules.remove(n - 1))
+                    })
+            });
+            if let Some(rule) = removed {
+                save_config(hc, conf_udata);
+                hc.print(&fm!("{IRC_MAGENTA}Rule removed: {}", rule));
+            } else {
+                hc.print(&fm!("{IRC_MAGENTA}\
+                         There's no rule {}. Use /LRULE LIST to see the \
+                         rules' numbers.", word[2]));
+            }
+        },
+        ("list", 2) | ("", 1) => {
+            let rules = conf_udata.apply(|conf: &Config| conf.rules.clone());
+            hc.print(&fm!("{IRC_CYAN}Rewrite rules:"));
+            if rules.is_empty() {
+                hc.print(&fm!("{IRC_CYAN}  (none)"));
+            }
+            for (i, rule) in rules.iter().enumerate() {
+                hc.print(&fm!("{IRC_CYAN}  {:3} {}", i + 1, rule));
+            }
+        },
+        ("test", 3..) => {
+            let network = hc.get_info("network").unwrap_or_default();
+            let channel = hc.get_info("channel").unwrap_or_default();
+            for (name, direction) in [("in", Direction::In), 
+                                      ("out", Direction::Out)] {
+                let rules = conf_udata.apply(
+                    |conf: &Config| {
+                        conf.rules_for(direction, &network, &channel)
+                    });
+                let pre   = rules::apply(&rules, Stage::Pre,  &word_eol[2]);
+                let post  = rules::apply(&rules, Stage::Post, &word_eol[2]);
+                hc.print(&fm!("{IRC_CYAN}  {:-4} pre:  {}", name, pre));
+                hc.print(&fm!("{IRC_CYAN}  {:-4} post: {}", name, post));
+            }
+        },
+        _ => {
+            hc.print(&fm!("USAGE: {}", LRULE_HELP));
+        }
+    }
+    Eat::All
+}
+
+/// Implements the /LGLOSSARY command, which manages the DeepL glossaries on
+/// the account and attaches them to channels. See `glossary.rs`. Requests
+/// to DeepL are made on a separate thread.
+///
+fn on_cmd_lglossary(hc         : &Hexchat,
+                    word       : &[String],
+                    word_eol   : &[String],
+                    conf_udata : &UserData)
+    -> Eat
+{
+    let (Some(network), Some(channel)) = (hc.get_info("network"),
+                                          hc.get_info("channel")) else {
+        hc.print(&fm!("{IRC_MAGENTA}\
+                 Failed to get channel information."));
+        return Eat::All;
+    };
+    let sub = word.get(1).map(|w| w.to_lowercase()).unwrap_or_default();
+    match (sub.as_str(), word.len()) {
+        ("list", 2) | ("", 1) => {
+            let attached = conf_udata.apply(
+                |conf: &Config| conf.channel(&network, &channel).glossaries);
+            thread::spawn(move || {
+                match glossary::list() {
+                    Ok(list) => {
+                        hc_print_th!("{IRC_CYAN}DeepL glossaries \
+                                      (* = used in this channel):");
+                        if list.is_empty() {
+                            hc_print_th!("{IRC_CYAN}  (none)");
+                        }
+                        for g in list {
+                            let used = attached.values()
+                                               .any(|id| *id == g.glossary_id);
+                            let mark = if used { "*" } else { " " };
+                            // DeepL's fields are shown, so they're cleaned
+                            // of anything that could be taken as a command.
+                            let id   = sanitize::sanitize(&g.glossary_id);
+                            let src  = sanitize::sanitize_lang_code(
+                                           &g.source_lang);
+                            let tgt  = sanitize::sanitize_lang_code(
+                                           &g.target_lang);
+                            let name = sanitize::sanitize(&g.name);
+                            hc_print_th!("{IRC_CYAN}{} {} {}>{} {:5} {}", 
+                                         mark, id, src, tgt, g.entry_count, 
+                                         name);
+                        }
+                    },
+                    Err(err) => {
+                        hc_print_th!("{IRC_MAGENTA}{}", err);
+                    }
+                }
+            });
+        },
+        ("local", 2) => {
+            let Some(dir) = local_glossary_dir(hc) else {
+                hc.print(&fm!("{IRC_MAGENTA}\
+                         Failed to get the Hexchat config directory."));
+                return Eat::All;
+            };
+            hc.print(&fm!("{IRC_CYAN}Local glossaries in {}:", dir.display()));
+            let pairs = glossary::local_glossaries(&dir);
+            if pairs.is_empty() {
+                hc.print(&fm!("{IRC_CYAN}  (none)"));
+            }
+            for (pair, entries) in pairs {
+                hc.print(&fm!("{IRC_CYAN}  {:-10}{} entries", pair, entries));
+            }
+        },
+        ("create", 6..) | ("upload", 6..) => {
+            let name = word[2].clone();
+            let (Some(src), Some(tgt)) = (find_lang(&word[3]), 
+                                          find_lang(&word[4])) else {
+                hc.print(&fm!("{IRC_MAGENTA}\
+                         BAD LANGUAGE PARAMETER. Use /LISTLANG to get a \
+                         list of supported languages."));
+                return Eat::All;
+            };
+            let (src, tgt) = (src.1.to_string(), tgt.1.to_string());
+            let entries = if sub == "create" {
+                // Inline entries: "term=translation; term=translation".
+                let lines = word_eol[5].split(';')
+                                       .map(|e| e.replacen('=', "\t", 1))
+                                       .collect::<Vec<_>>()
+                                       .join("\n");
+                glossary::parse_entries(&lines)
+            } else {
+                glossary::read_entries(&word_eol[5])
+            };
+            let entries = match entries {
+                Ok(entries) => entries,
+                Err(err) => {
+                    hc.print(&fm!("{IRC_MAGENTA}{}", err));
+                    return Eat::All;
+                }
+            };
+            thread::spawn(move || {
+                match glossary::create(&name, &src, &tgt, &entries) {
+                    Ok(g) => {
+                        let id   = sanitize::sanitize(&g.glossary_id);
+                        let name = sanitize::sanitize(&g.name);
+                        hc_print_th!("{IRC_MAGENTA}Glossary '{}' created \
+                                      with {} entries; its ID is {}. Use \
+                                      /LGLOSSARY USE {} to use it in a \
+                                      channel.", name, entries.len(), 
+                                      id, id);
+                    },
+                    Err(err) => {
+                        hc_print_th!("{IRC_MAGENTA}{}", err);
+                    }
+                }
+            });
+        },
+        ("delete", 3) => {
+            let id = word[2].clone();
+            // Detach the glossary from every channel using it.
+            conf_udata.apply_mut(
+                |conf: &mut Config| {
+                    for settings in conf.channels.values_mut()
+                                                 .flat_map(|c| c.values_mut()) {
+                        settings.glossaries.retain(|_, gid| *gid != id);
+                    }
+                });
+            save_config(hc, conf_udata);
+            thread::spawn(move || {
+                match glossary::delete(&id) {
+                    Ok(()) => {
+                        hc_print_th!("{IRC_MAGENTA}Glossary {} deleted.", id);
+                    },
+                    Err(err) => {
+                        hc_print_th!("{IRC_MAGENTA}{}", err);
+                    }
+                }
+            });
+        },
+        ("use", 3) if word[2].eq_ignore_ascii_case("off") => {
+            conf_udata.apply_mut(
+                |conf: &mut Config| {
+                    conf.channel_mut(&network, &channel).glossaries.clear()
+                });
+            save_config(hc, conf_udata);
+            hc.print(&fm!("{IRC_MAGENTA}\
+                     Glossaries are no longer used in {}.", channel));
+        },
+        ("use", 3) => {
+            // Look up the glossary's languages, then attach it with them to
+            // the channel the command was given in. The settings can only be
+            // reached from the main thread, so a timer there waits for the
+            // answer.
+            let id = word[2].clone();
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                let _ = tx.send(glossary::get(&id));
+            });
+            let conf_udata = conf_udata.clone();
+            hc.hook_timer(GLOSSARY_POLL_MS, move |hc, _| {
+                let g = match rx.try_recv() {
+                    Ok(Ok(g)) => g,
+                    Ok(Err(err)) => {
+                        hc.print(&fm!("{IRC_MAGENTA}{}", err));
+                        return 0;
+                    },
+                    Err(mpsc::TryRecvError::Empty) => return 1,
+                    Err(mpsc::TryRecvError::Disconnected) => return 0,
+                };
+                let id  = sanitize::sanitize(&g.glossary_id);
+                let src = sanitize::sanitize_lang_code(&g.source_lang);
+                let tgt = sanitize::sanitize_lang_code(&g.target_lang);
+                let (Some(src), Some(tgt)) = (find_lang(&src), 
+                                              find_lang(&tgt)) else {
+                    hc.print(&fm!("{IRC_MAGENTA}\
+                             Glossary {} is for languages that aren't \
+                             supported.", id));
+                    return 0;
+                };
+                let key = glossary::pair_key(src.1, tgt.1);
+                conf_udata.apply_mut(
+                    |conf: &mut Config| {
+                        conf.channel_mut(&network, &channel)
+                            .glossaries.insert(key, id.clone())
+                    });
+                save_config(hc, &conf_udata);
+                hc.print(&fm!("{IRC_MAGENTA}\
+                         Glossary {} will be used for {} to {} translations \
+                         in {}.", id, src.0, tgt.0, channel));
+                0
+            }, NoData);
+        },
+        ("use", 5) => {
+            let (Some(src), Some(tgt)) = (find_lang(&word[3]), 
+                                          find_lang(&word[4])) else {
+                hc.print(&fm!("{IRC_MAGENTA}\
+                         BAD LANGUAGE PARAMETER. Use /LISTLANG to get a \
+                         list of supported languages."));
+                return Eat::All;
+            };
+            let key = glossary::pair_key(src.1, tgt.1);
+            conf_udata.apply_mut(
+                |conf: &mut Config| {
+                    conf.channel_mut(&network, &channel)
+                        .glossaries.insert(key, word[2].clone())
+                });
+            save_config(hc, conf_udata);
+            hc.print(&fm!("{IRC_MAGENTA}\
+                     Glossary {} will be used for {} to {} translations \
+                     in {}.", word[2], src.0, tgt.0, channel));
+        },
+        _ => {
+            hc.print(&fm!("USAGE: {}", LGLOSSARY_HELP));
+        }
+    }
+    Eat::All
+}
+
 /// Finds and gives back a tuple (<long-name>, <abbrev>) from the supported 
 /// languages list. This can be used to verify the languages the user requested
 /// to see if they exist and can be used to interact with translation services.
 /// # Arguments
 /// * `lang` - This can be the name of the langauge, or the two character code
-///            for the language.
+///   for the language.
 /// # Returns
 /// * If a match is found, a tuple is returned from the `SUPPORTED_LANGUAGES`
 ///   array. It will have the long name for the language and its two character
 ///   code. 
 ///
 fn find_lang(lang: &str) -> Option<&(&str, &str)> {
+    // The list is padded with empty entries that mustn't match.
+    if lang.is_empty() {
+        return None;
+    }
     let lang = lang.to_lowercase();
     #[allow(clippy::manual_find)]
     for lang_info in &SUPPORTED_LANGUAGES {
@@ -652,6 +2688,17 @@ fn find_lang(lang: &str) -> Option<&(&str, &str)> {
     None
 }
 
+/// Like `find_lang()`, but also accepts "auto", for which `AUTO_LANG_INFO`
+/// is returned.
+///
+fn find_lang_or_auto(lang: &str) -> Option<&(&str, &str)> {
+    if lang.eq_ignore_ascii_case(AUTO_LANG) {
+        Some(&AUTO_LANG_INFO)
+    } else {
+        find_lang(lang)
+    }
+}
+
 /// Translation error. The error object will contain either a mix of translated
 /// and untranslated messages - if some succeeded and some didn't. Or, just
 /// untranslated text accessible from `get_partial_trans()`. The display
@@ -670,11 +2717,11 @@ impl TranslationError {
     /// Constructs the translation error.
     /// # Arguments
     /// * `partial_trans`   - Translated and untranslated portions of the 
-    ///                       original text.
+    ///   original text.
     /// * `error_msg`       - The aggregate of error messages that occurred
-    ///                       during the translation.
+    ///   during the translation.
     /// * `over_limit`      - A bool indicating whether the server responded
-    ///                       with a 403 error.
+    ///   with a 403 error.
     ///
     fn new(partial_trans: String, error_msg: String, over_limit: bool) -> Self {
         TranslationError { partial_trans, error_msg, over_limit }
@@ -724,8 +2771,14 @@ const LISTLANG_HELP: &str = "/LISTLANG - Lists languages supported and \
                              their abbrevations. This command takes no \
                              parameters.";
                              
-const SETLANG_HELP : &str = "/SETLANG <src> <tgt> - Sets source and target \
-                             languages for the channel.";
+const SETLANG_HELP : &str = "/SETLANG <src> <tgt> [<formality>] - Sets \
+                             source and target languages for the channel. \
+                             <tgt> can be 'auto' to detect the language of \
+                             incoming messages, or a comma separated list, \
+                             e.g. 'es,fr,de', to send messages in several \
+                             languages. <formality> can be default, more, \
+                             less, prefer_more or prefer_less, for the \
+                             languages that support it.";
                              
 const OFFLANG_HELP : &str = "/OFFLANG - Deactivates translation on the \
                              channel. This command takes no paramters.";
@@ -736,6 +2789,104 @@ const LSAY_HELP    : &str = "/LSAY <message> - Sends a translated message \
 const LME_HELP     : &str = "/LME <message> - Sends a channel action \
                              message translated.";
 
+const LTOPIC_HELP  : &str = "/LTOPIC <topic> - Sets the channel's topic \
+                             translated.";
+
+const LMSG_HELP    : &str = "/LMSG <nick|channel> [-l <lang>] <message> - \
+                             Sends a private message translated into the \
+                             language of the nick or channel, or the one \
+                             given with -l. Works from any window.";
+
+const LNOTICE_HELP : &str = "/LNOTICE <nick|channel> [-l <lang>] <message> \
+                             - Sends a notice translated like /LMSG does.";
+
+const LUSER_HELP   : &str = "/LUSER [<nick> <lang|auto|off>] - Sets the \
+                             language messages from the nick are translated \
+                             from on this network, or removes it with 'off'. \
+                             Without parameters, lists the nicks that have \
+                             a language set.";
+
+const LFORMALITY_HELP: &str = "/LFORMALITY [<nick> <formality|off>] - Sets \
+                             the formality of messages to the nick on this \
+                             network, in its query window or addressed to \
+                             it with 'nick:', overriding the channel's: \
+                             default, more, less, prefer_more or \
+                             prefer_less. Use 'off' to remove it. Without \
+                             parameters, lists the nicks with one set.";
+
+const LSET_HELP    : &str = "/LSET [-global] [<option> <value>] - Changes a \
+                             translation setting for the channel. Without \
+                             parameters, lists the channel's settings. \
+                             Options: separate <on|off> - send translations \
+                             into several languages on separate lines. \
+                             autosay <on|off> - translate typed text as if \
+                             sent with /LSAY; start a line with \\ to send \
+                             it untranslated. confirm <on|off> - preview \
+                             /LSAY and /LME messages like /LPREVIEW instead \
+                             of sending them. backcheck <on|off> - show \
+                             sent messages translated back into your \
+                             language, flagging doubtful ones. notices, \
+                             parts, quits, kicks <on|off> - also translate \
+                             notices, and part, quit and kick reasons. \
+                             topics <on|off> - translate the topic, on by \
+                             default. formality <level> - default, more, \
+                             less, prefer_more or prefer_less. context <n> - \
+                             the number of recent lines sent to DeepL as \
+                             context, 0 to 20. model \
+                             <latency|quality|prefer_quality> - DeepL's model \
+                             type. split <on|off|nonewlines> - how DeepL \
+                             splits sentences. preserve <on|off> - keep \
+                             punctuation and capitalization as written. These \
+                             three can be 'default', and can be set for all \
+                             channels with -global.";
+
+const LSTATUS_HELP : &str = "/LSTATUS - Shows the languages and the \
+                             translation settings in effect for the \
+                             channel.";
+
+const LPREVIEW_HELP: &str = "/LPREVIEW <message> - Shows the translation of \
+                             the message, and its translation back into \
+                             your language, without sending it. The \
+                             translation is placed in the input box.";
+
+const LWHO_HELP    : &str = "/LWHO [<nick>] | /LWHO FORGET [<nick>] - Lists \
+                             the languages learned for nicks on this \
+                             network, which are used to translate their \
+                             messages; or forgets them.";
+
+const LDETECT_HELP : &str = "/LDETECT <text> - Identifies the language of \
+                             the text locally, without using the \
+                             translation service.";
+
+const LRULE_HELP   : &str = "/LRULE ADD [-pre|-post] [-in|-out] [-here] \
+                             <pattern> [<replacement>] | /LRULE DEL <n> | \
+                             /LRULE LIST | /LRULE TEST <text> - Manages \
+                             rules that rewrite matches of a regular \
+                             expression before (-pre, the default) or after \
+                             (-post) translation, for incoming (-in) or \
+                             outgoing (-out) messages or both, in every \
+                             channel or just this one (-here). Use \\s for \
+                             spaces in the pattern, and $1 and so on for \
+                             its groups in the replacement.";
+
+const LGLOSSARY_HELP: &str = "/LGLOSSARY LIST | /LGLOSSARY CREATE <name> \
+                             <src> <tgt> <term>=<translation>[; ...] | \
+                             /LGLOSSARY UPLOAD <name> <src> <tgt> <file> | \
+                             /LGLOSSARY DELETE <id> | /LGLOSSARY USE \
+                             <id|off> | /LGLOSSARY LOCAL - Manages the \
+                             DeepL glossaries that fix how terms are \
+                             translated. UPLOAD reads the entries from a TSV \
+                             or CSV file. USE makes the channel's \
+                             translations between the glossary's languages \
+                             use it; OFF stops using glossaries in the \
+                             channel. LOCAL lists the local glossaries, \
+                             used for languages without a DeepL glossary.";
+
+/// Language info for the "auto" pseudo-language accepted by `/SETLANG` as the
+/// other party's language.
+///
+const AUTO_LANG_INFO: (&str, &str) = ("Auto-detect", AUTO_LANG);
+
 // A listing of all the supported langauges.
 
 /// Supported languages by DeepL API
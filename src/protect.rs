@@ -0,0 +1,196 @@
+//! Finds the parts of a message that shouldn't be translated: nicks of the
+//! channel's users, URLs, channel names, inline `code` and emoji. The
+//! translation service would otherwise translate nicks that happen to be
+//! ordinary words, and mangle URLs and channel names. The spans found are
+//! marked in the markup sent for translation so the service leaves them
+//! as they are.
+//!
+
+use regex::Regex;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::sync::OnceLock;
+
+/// Matches URLs, channel names, inline code and emoji. Trailing punctuation
+/// is left out of URLs and channel names, as it usually belongs to the
+/// sentence around them. A URL's closing parentheses are put back by 
+/// `balance_parens()` when they close one opened in the URL.
+///
+const PATTERN: &str = concat!(
+    r"(?:\b(?:https?|ftp)://|\bwww\.)[^\s\x00-\x1F<>]*[^\s\x00-\x1F<>.,;:!?)'\x22]",
+    r"|\B#[^\s,\x00-\x1F]*[^\s,\x00-\x1F.;:!?)'\x22]",
+    r"|`[^`\x00-\x1F]+`",
+    r"|\p{Extended_Pictographic}",
+    r"(?:\x{FE0F}|[\x{1F3FB}-\x{1F3FF}]|\x{200D}\p{Extended_Pictographic})*",
+);
+
+/// Gives the byte ranges of the spans of `text` to keep untranslated, in
+/// order and without overlaps.
+/// # Arguments
+/// * `text`  - The message to be translated.
+/// * `nicks` - The nicks of the users in the channel.
+///
+pub fn spans(text: &str, nicks: &[String]) -> Vec<Range<usize>> {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    let regex = REGEX.get_or_init(|| Regex::new(PATTERN).unwrap());
+
+    let mut spans = regex.find_iter(text)
+                         .map(|m| balance_parens(text, m.range()))
+                         .collect::<Vec<_>>();
+    spans.extend(nick_spans(text, nicks));
+    merge(spans)
+}
+
+/// Extends a URL found in `text` at `span` over the closing parentheses
+/// right after it that close ones opened within it, as in
+/// "https://en.wikipedia.org/wiki/Rust_(language)".
+///
+fn balance_parens(text: &str, mut span: Range<usize>) -> Range<usize> {
+    let url = &text[span.clone()];
+    if url.starts_with(['#', '`']) {
+        return span;
+    }
+    let mut open = url.matches('(').count()
+                       .saturating_sub(url.matches(')').count());
+    while open > 0 && text[span.end..].starts_with(')') {
+        span.end += 1;
+        open     -= 1;
+    }
+    span
+}
+
+/// Sorts byte ranges and merges those that overlap.
+///
+pub fn merge(mut spans: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    spans.sort_by_key(|s| (s.start, s.end));
+
+    let mut merged: Vec<Range<usize>> = vec![];
+    for span in spans {
+        match merged.last_mut() {
+            Some(last) if span.start <= last.end => {
+                last.end = last.end.max(span.end);
+            },
+            _ => merged.push(span),
+        }
+    }
+    merged
+}
+
+/// Gives the byte ranges of the words in `text` that are one of `nicks`,
+/// ignoring case.
+///
+fn nick_spans(text: &str, nicks: &[String]) -> Vec<Range<usize>> {
+    let nicks = nicks.iter()
+                     .map(|n| n.to_lowercase())
+                     .collect::<HashSet<_>>();
+    let mut spans = vec![];
+    let mut start = None;
+
+    for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (is_nick_char(c), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                if nicks.contains(&text[s..i].to_lowercase()) {
+                    spans.push(s..i);
+                }
+                start = None;
+            },
+            _ => {}
+        }
+    }
+    spans
+}
+
+/// Indicates whether `c` can be part of an IRC nick.
+///
+fn is_nick_char(c: char) -> bool {
+    c.is_alphanumeric() || "[]\\`_^{|}-".contains(c)
+}
+
+/// Splits a leading addressing prefix, such as "alice: " or "bob, ", from
+/// `text`. The prefix is only recognized if it names one of `nicks`, and
+/// there's more text after it.
+/// # Returns
+/// * The prefix, including the whitespace after it, and the rest of the
+///   text. If there's no prefix, it's empty and the rest is all of `text`.
+///
+pub fn split_address<'a>(text  : &'a str, 
+                         nicks : &[String]) 
+    -> (&'a str, &'a str) 
+{
+    let end  = text.find(|c| !is_nick_char(c)).unwrap_or(text.len());
+    let nick = &text[..end];
+    let rest = &text[end..];
+
+    let is_nick = !nick.is_empty()
+               && nicks.iter().any(|n| n.to_lowercase() == nick.to_lowercase());
+    match rest.strip_prefix([':', ',']) {
+        Some(after) if is_nick && after.starts_with(char::is_whitespace) => {
+            let body  = after.trim_start();
+            let split = text.len() - body.len();
+            if body.is_empty() {
+                ("", text)
+            } else {
+                text.split_at(split)
+            }
+        },
+        _ => ("", text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protected(text: &str) -> Vec<&str> {
+        spans(text, &[]).into_iter().map(|s| &text[s]).collect()
+    }
+
+    #[test]
+    fn url_keeps_balanced_parens() {
+        assert_eq!(protected("see https://example.com/a_(b) now"),
+                   ["https://example.com/a_(b)"]);
+        assert_eq!(protected("(https://example.com/a_(b))."),
+                   ["https://example.com/a_(b)"]);
+    }
+
+    #[test]
+    fn url_leaves_out_enclosing_parens() {
+        assert_eq!(protected("(see https://example.com/a)"),
+                   ["https://example.com/a"]);
+        assert_eq!(protected("go to www.example.com."),
+                   ["www.example.com"]);
+    }
+
+    fn nicks() -> Vec<String> {
+        vec!["alice".to_string(), "[bob]".to_string()]
+    }
+
+    #[test]
+    fn address_with_colon_or_comma() {
+        assert_eq!(split_address("alice: hi there", &nicks()),
+                   ("alice: ", "hi there"));
+        assert_eq!(split_address("[bob], hi", &nicks()), ("[bob], ", "hi"));
+        assert_eq!(split_address("ALICE:   hi", &nicks()), ("ALICE:   ", "hi"));
+    }
+
+    #[test]
+    fn address_needs_space_after_separator() {
+        assert_eq!(split_address("alice:hi", &nicks()), ("", "alice:hi"));
+        assert_eq!(split_address("alice,bob", &nicks()), ("", "alice,bob"));
+    }
+
+    #[test]
+    fn address_needs_text_after_it() {
+        assert_eq!(split_address("alice: ", &nicks()), ("", "alice: "));
+        assert_eq!(split_address("alice:", &nicks()), ("", "alice:"));
+    }
+
+    #[test]
+    fn address_must_name_a_nick() {
+        assert_eq!(split_address("note: call me", &nicks()),
+                   ("", "note: call me"));
+        assert_eq!(split_address("alice hi", &nicks()), ("", "alice hi"));
+        assert_eq!(split_address(": hi", &nicks()), ("", ": hi"));
+    }
+}
@@ -0,0 +1,223 @@
+//! User-defined rewrite rules, managed with `/LRULE`. Each rule replaces
+//! matches of a regular expression, either before a message is translated -
+//! to expand slang like "brb" the translation service doesn't know - or
+//! after, to fix quirks the service keeps repeating. Rules can be limited to
+//! incoming or outgoing messages, and to a single channel.
+//!
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::casemap;
+
+/// Which messages a rule applies to.
+///
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Messages received from others.
+    In,
+    /// Messages the user sends.
+    Out,
+    /// All messages.
+    Both,
+}
+
+/// When a rule is applied.
+///
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    /// To the text before it's translated.
+    Pre,
+    /// To the translation.
+    Post,
+}
+
+/// A rewrite rule.
+///
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Rule {
+    /// The regular expression to match.
+    pub pattern     : String,
+
+    /// What matches are replaced with. `$1`, `${name}` and so on refer to
+    /// the expression's groups.
+    pub replacement : String,
+
+    pub direction   : Direction,
+    pub stage       : Stage,
+
+    /// The channel the rule is limited to, if any, folded as its network
+    /// folds names. See `casemap::fold()`.
+    #[serde(default)]
+    pub channel     : Option<String>,
+
+    /// The network of the channel the rule is limited to.
+    #[serde(default)]
+    pub network     : Option<String>,
+}
+
+impl Rule {
+    /// Creates a rule, checking that its pattern is a valid regular
+    /// expression. `place` is the network and channel the rule is limited
+    /// to, if any.
+    /// # Returns
+    /// * `Err()` with a description of the problem if the pattern isn't
+    ///   valid.
+    ///
+    pub fn new(pattern     : &str,
+               replacement : &str,
+               direction   : Direction,
+               stage       : Stage,
+               place       : Option<(String, String)>)
+        -> Result<Self, String>
+    {
+        Regex::new(pattern).map_err(|err| err.to_string())?;
+        let (network, channel) = match place {
+            Some((network, channel)) => {
+                let channel = casemap::fold(&network, &channel);
+                (Some(network), Some(channel))
+            },
+            None => (None, None),
+        };
+        Ok(Rule { pattern     : pattern.to_string(),
+                  replacement : replacement.to_string(),
+                  direction,
+                  stage,
+                  channel,
+                  network })
+    }
+
+    /// Indicates whether the rule applies to messages going in `direction`
+    /// in `channel` on `network`.
+    ///
+    pub fn applies(&self, 
+                   direction : Direction, 
+                   network   : &str, 
+                   channel   : &str) 
+        -> bool 
+    {
+        // Rules saved before channels were folded, and before the network
+        // was kept, hold the channel as it was typed and no network.
+        let fold = |name: &str| casemap::fold(network, name);
+        (self.direction == Direction::Both || self.direction == direction)
+            && self.network.as_ref().is_none_or(|n| n == network)
+            && self.channel.as_ref().is_none_or(|c| fold(c) == fold(channel))
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            Direction::In   => "in",
+            Direction::Out  => "out",
+            Direction::Both => "in/out",
+        };
+        let stage = match self.stage {
+            Stage::Pre  => "pre",
+            Stage::Post => "post",
+        };
+        write!(f, "[{} {}", stage, direction)?;
+        if let Some(channel) = &self.channel {
+            write!(f, " {}", channel)?;
+        }
+        if let Some(network) = &self.network {
+            write!(f, " on {}", network)?;
+        }
+        write!(f, "] {} -> {}", self.pattern, self.replacement)
+    }
+}
+
+/// Applies the rules for `stage` to `text`, in order. Rules whose pattern
+/// no longer compiles are skipped.
+///
+pub fn apply(rules: &[Rule], stage: Stage, text: &str) -> String {
+    let mut text = text.to_string();
+    for rule in rules.iter().filter(|r| r.stage == stage) {
+        if let Ok(regex) = Regex::new(&rule.pattern) {
+            text = regex.replace_all(&text, rule.replacement.as_str())
+                        .into_owned();
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, replacement: &str, stage: Stage) -> Rule {
+        Rule::new(pattern, replacement, Direction::Both, stage, None).unwrap()
+    }
+
+    fn in_channel(direction: Direction, network: &str, channel: &str) -> Rule {
+        Rule::new("x", "y", direction, Stage::Pre,
+                  Some((network.to_string(), channel.to_string()))).unwrap()
+    }
+
+    #[test]
+    fn apply_runs_rules_of_the_stage_in_order() {
+        let rules = [rule(r"\bbrb\b", "be right back", Stage::Pre),
+                     rule("back", "BACK", Stage::Pre),
+                     rule("right", "left", Stage::Post)];
+        assert_eq!(apply(&rules, Stage::Pre, "brb, brb"),
+                   "be right BACK, be right BACK");
+        assert_eq!(apply(&rules, Stage::Post, "brb right"), "brb left");
+    }
+
+    #[test]
+    fn apply_expands_groups() {
+        let rules = [rule(r"(\w+)@(\w+)", "$2 at $1", Stage::Post)];
+        assert_eq!(apply(&rules, Stage::Post, "me@home"), "home at me");
+    }
+
+    #[test]
+    fn apply_skips_patterns_that_no_longer_compile() {
+        let mut broken = rule("a", "b", Stage::Pre);
+        broken.pattern = "(".to_string();
+        let rules = [broken, rule("c", "d", Stage::Pre)];
+        assert_eq!(apply(&rules, Stage::Pre, "abc"), "abd");
+    }
+
+    #[test]
+    fn new_rejects_invalid_patterns() {
+        assert!(Rule::new("(", "", Direction::In, Stage::Pre, None).is_err());
+    }
+
+    #[test]
+    fn applies_filters_by_direction() {
+        let incoming = Rule::new("x", "y", Direction::In, Stage::Pre, None)
+                           .unwrap();
+        assert!(incoming.applies(Direction::In, "net", "#chan"));
+        assert!(!incoming.applies(Direction::Out, "net", "#chan"));
+
+        let both = rule("x", "y", Stage::Pre);
+        assert!(both.applies(Direction::In, "net", "#chan"));
+        assert!(both.applies(Direction::Out, "net", "#chan"));
+    }
+
+    #[test]
+    fn applies_to_the_channel_however_it_is_cased() {
+        let r = in_channel(Direction::Both, "test-rules-net", "#Rust[Dev]");
+        assert_eq!(r.channel.as_deref(), Some("#rust{dev}"));
+        assert!(r.applies(Direction::In, "test-rules-net", "#RUST{dev}"));
+        assert!(!r.applies(Direction::In, "test-rules-net", "#other"));
+    }
+
+    #[test]
+    fn applies_only_on_its_network() {
+        let r = in_channel(Direction::Both, "libera", "#rust");
+        assert!(r.applies(Direction::Out, "libera", "#rust"));
+        assert!(!r.applies(Direction::Out, "oftc", "#rust"));
+    }
+
+    #[test]
+    fn old_rules_without_a_network_apply_on_any() {
+        let mut r = in_channel(Direction::Both, "libera", "#rust");
+        r.network = None;
+        r.channel = Some("#Rust".to_string());
+        assert!(r.applies(Direction::In, "oftc", "#rust"));
+    }
+}
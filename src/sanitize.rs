@@ -0,0 +1,103 @@
+//! Cleans text received from the translation service before it's sent to
+//! IRC or printed. A translation containing a CR or LF could otherwise
+//! inject extra IRC commands when it's passed to `/SAY`, and other control
+//! characters or Unicode bidirectional overrides could disguise what's
+//! displayed or sent.
+//!
+
+/// Gives a copy of `text` that is safe to send in a single IRC message and
+/// to print. Line breaks and tabs become spaces; other control characters
+/// (including IRC formatting codes) and bidirectional override, embedding
+/// and isolate characters are removed.
+///
+pub fn sanitize(text: &str) -> String {
+    sanitize_keeping(text, |_| false)
+}
+
+/// Like `sanitize()`, but keeps the mIRC formatting codes for bold, colors,
+/// italics and so on.
+///
+pub fn sanitize_irc(text: &str) -> String {
+    sanitize_keeping(text, |c| matches!(c, '\x02' | '\x03' | '\x04' | '\x0F'
+                                         | '\x11' | '\x16' | '\x1D' | '\x1E'
+                                         | '\x1F'))
+}
+
+/// Sanitizes `text`, keeping the control characters `keep` accepts.
+///
+fn sanitize_keeping(text: &str, keep: fn(char) -> bool) -> String {
+    let mut clean = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\r' | '\n' | '\t' | '\u{2028}' | '\u{2029}' => {
+                if !clean.ends_with(' ') {
+                    clean.push(' ');
+                }
+            },
+            c if keep(c) => clean.push(c),
+            c if c.is_control() || is_bidi_control(c) => {},
+            c => clean.push(c),
+        }
+    }
+    clean
+}
+
+/// Gives a copy of a language code with anything but ASCII letters, digits
+/// and '-' removed.
+///
+pub fn sanitize_lang_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+        .collect()
+}
+
+/// Indicates whether `c` is one of the Unicode characters that override the
+/// direction of the text around it.
+///
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_breaks_become_single_spaces() {
+        assert_eq!(sanitize("one\r\ntwo\nthree\rfour"), "one two three four");
+        assert_eq!(sanitize("a\tb\u{2028}c\u{2029}d"), "a b c d");
+    }
+
+    #[test]
+    fn no_command_can_be_injected() {
+        let text = sanitize_irc("hi\r\nPRIVMSG #chan :owned\n/QUIT");
+        assert!(!text.contains(['\r', '\n']));
+        assert_eq!(text, "hi PRIVMSG #chan :owned /QUIT");
+    }
+
+    #[test]
+    fn ctcp_and_other_controls_are_removed() {
+        assert_eq!(sanitize("\x01ACTION dances\x01"), "ACTION dances");
+        assert_eq!(sanitize("a\x00b\x07c\x1Bd\x7Fe"), "abcde");
+    }
+
+    #[test]
+    fn bidi_controls_are_removed() {
+        assert_eq!(sanitize("abc\u{202E}fed\u{202C}"), "abcfed");
+        assert_eq!(sanitize("\u{2066}x\u{2067}y\u{2068}z\u{2069}"), "xyz");
+    }
+
+    #[test]
+    fn formatting_is_kept_only_by_sanitize_irc() {
+        let text = "\x02bold\x02 \x0304,01red\x03 \x1Dit\x1D\x0F";
+        assert_eq!(sanitize_irc(text), text);
+        assert_eq!(sanitize(text), "bold 04,01red it");
+        assert_eq!(sanitize_irc("\x01x\x01"), "x");
+    }
+
+    #[test]
+    fn lang_codes_keep_only_safe_characters() {
+        assert_eq!(sanitize_lang_code("EN-us"), "EN-us");
+        assert_eq!(sanitize_lang_code("de\r\n; rm"), "derm");
+    }
+}
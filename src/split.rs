@@ -0,0 +1,205 @@
+//! Splits outgoing messages that are too long for a single IRC line. IRC
+//! limits lines to 512 bytes, including the prefix the server adds, so long
+//! translations - especially into languages whose letters take several bytes
+//! in UTF-8 - would otherwise be cut off by the server. Messages are split
+//! between sentences where possible, then between words, and as a last
+//! resort between grapheme clusters, so no character is ever cut in two.
+//!
+
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::format::Styled;
+
+/// The maximum length of an IRC line, including the trailing CR-LF.
+///
+const IRC_LINE_BYTES: usize = 512;
+
+/// The longest user name the server may put in the `nick!user@host` prefix
+/// of relayed messages, including a leading '~'.
+///
+const MAX_USER_BYTES: usize = 11;
+
+/// The longest host name the server may put in the prefix of relayed
+/// messages.
+///
+const MAX_HOST_BYTES: usize = 63;
+
+/// The bytes CTCP ACTION adds around the text of a `/ME` message:
+/// "\x01ACTION " and "\x01".
+///
+const ACTION_BYTES: usize = 9;
+
+/// Gives the number of bytes of message text that can be sent in one line
+/// to `target` without the line the server relays to others exceeding IRC's
+/// limit.
+/// # Arguments
+/// * `nick`      - The user's nick.
+/// * `target`    - The channel or nick the message is sent to.
+/// * `is_action` - Whether the message is sent with `/ME`.
+///
+pub fn max_message_bytes(nick: &str, target: &str, is_action: bool) -> usize {
+    // :nick!user@host PRIVMSG target :text\r\n
+    let overhead = 1 + nick.len() + 1 + MAX_USER_BYTES + 1 + MAX_HOST_BYTES
+                 + " PRIVMSG ".len() + target.len() + " :".len() + 2
+                 + if is_action { ACTION_BYTES } else { 0 };
+    IRC_LINE_BYTES.saturating_sub(overhead).max(1)
+}
+
+/// Splits `text` into lines of at most `max_bytes` bytes each. Text that
+/// fits is returned as a single line. Formatting is carried over the 
+/// splits: each line opens with the codes for the style in effect where it
+/// starts, so it's displayed as it would have been in one long line.
+///
+pub fn split_message(text: &str, max_bytes: usize) -> Vec<String> {
+    if text.len() <= max_bytes {
+        return vec![text.to_string()];
+    }
+    let styled = Styled::new(text);
+    let plain  = styled.plain();
+    let mut lines = vec![];
+
+    // The line being built is the range `start..end` of the plain text.
+    let mut start = 0;
+    let mut end   = 0;
+
+    for (s, sentence) in plain.split_sentence_bound_indices() {
+        let sentence_end = s + sentence.len();
+        if fits(&styled, start..sentence_end, max_bytes) {
+            end = sentence_end;
+            continue;
+        }
+        flush(&styled, &mut lines, &mut start, end);
+
+        if fits(&styled, start..sentence_end, max_bytes) {
+            end = sentence_end;
+            continue;
+        }
+        for (w, word) in sentence.split_word_bound_indices() {
+            let word_end = s + w + word.len();
+            if fits(&styled, start..word_end, max_bytes) {
+                end = word_end;
+                continue;
+            }
+            flush(&styled, &mut lines, &mut start, end);
+
+            for (g, grapheme) in word.grapheme_indices(true) {
+                let grapheme_end = s + w + g + grapheme.len();
+                if !fits(&styled, start..grapheme_end, max_bytes) {
+                    flush(&styled, &mut lines, &mut start, end);
+                }
+                end = grapheme_end;
+            }
+        }
+    }
+    flush(&styled, &mut lines, &mut start, end);
+    lines
+}
+
+/// Gives `range` of `plain` without the whitespace at either end, which is
+/// trimmed from lines.
+///
+fn trimmed(plain: &str, range: Range<usize>) -> Range<usize> {
+    let text  = &plain[range.clone()];
+    let start = range.start + (text.len() - text.trim_start().len());
+    let end   = range.end - (text.len() - text.trim_end().len());
+    start..end.max(start)
+}
+
+/// Indicates whether the range of the plain text `range`, with the codes
+/// that format it, fits in `max_bytes`. Whitespace at either end of a line 
+/// doesn't count, as it's trimmed.
+///
+fn fits(styled: &Styled, range: Range<usize>, max_bytes: usize) -> bool {
+    let range = trimmed(styled.plain(), range);
+    styled.render(range).len() <= max_bytes
+}
+
+/// Moves the line being built, `start..end` of the plain text, trimmed and
+/// formatted, onto the finished lines. The next line starts at `end`.
+///
+fn flush(styled : &Styled, 
+         lines  : &mut Vec<String>, 
+         start  : &mut usize, 
+         end    : usize)
+{
+    let range = trimmed(styled.plain(), *start..end);
+    if !range.is_empty() {
+        lines.push(styled.render(range));
+    }
+    *start = end;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_text_is_one_line() {
+        assert_eq!(split_message("hello there", 20), ["hello there"]);
+    }
+
+    #[test]
+    fn splits_between_sentences_then_words() {
+        let lines = split_message("One two. Three four five.", 12);
+        assert_eq!(lines, ["One two.", "Three four", "five."]);
+    }
+
+    #[test]
+    fn lines_never_exceed_the_limit() {
+        let text = "Ünïcödé wörds with ümläuts and ∑ symbols. ".repeat(20);
+        for max in [5, 17, 40, 100] {
+            for line in split_message(&text, max) {
+                assert!(line.len() <= max, "{:?} is over {}", line, max);
+            }
+        }
+    }
+
+    #[test]
+    fn never_cuts_a_character_or_grapheme() {
+        // Each family emoji is one grapheme of several code points.
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let text   = family.repeat(4);
+        let lines  = split_message(&text, family.len() + 3);
+        assert_eq!(lines, vec![family; 4]);
+
+        let text  = "дддддддддд";
+        let lines = split_message(text, 5);
+        assert!(lines.iter().all(|l| l == "дд"));
+        assert_eq!(lines.concat(), text);
+    }
+
+    #[test]
+    fn an_oversized_grapheme_gets_its_own_line() {
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let lines  = split_message(&format!("ab {} cd", family), 4);
+        assert_eq!(lines, ["ab", family, "cd"]);
+    }
+
+    #[test]
+    fn formatting_carries_over_splits() {
+        let text  = "\x02bold\x02 and \x0304red\x03 ".repeat(10);
+        let lines = split_message(&text, 60);
+        assert!(lines.len() > 1);
+        for line in &lines {
+            assert!(line.len() <= 60);
+            assert!(!line.ends_with(['\x02', '\x03', '\x0F']),
+                    "{:?} ends with a code", line);
+        }
+        // Each line shows its text as it was styled in the whole message.
+        let whole = Styled::new(&text);
+        let mut cursor = 0;
+        for line in &lines {
+            let plain = Styled::new(line).plain().to_string();
+            let start = cursor + whole.plain()[cursor..].find(&plain).unwrap();
+            cursor    = start + plain.len();
+            assert_eq!(&whole.render(start..cursor), line);
+        }
+    }
+
+    #[test]
+    fn continuation_lines_reopen_the_style() {
+        let lines = split_message("\x02one two three\x02", 9);
+        assert_eq!(lines, ["\x02one two", "\x02three"]);
+    }
+}